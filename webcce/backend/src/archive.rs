@@ -0,0 +1,375 @@
+use axum::{
+    body::Bytes,
+    extract::{Multipart, Path, State},
+    http::{header, StatusCode},
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Write};
+use tracing::{error, info};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::assets::{as_text, form_error};
+use crate::files::{update_projects, validate_file_name};
+use crate::projects::{validate_project_name, ProjectSummary};
+use crate::revisions::{self, SaveKind};
+use crate::session::AuthUser;
+use crate::state::{self, AppState, File, Project};
+use crate::storage::Storage;
+
+// Limit for an uploaded archive.
+pub const MAX_IMPORT_SIZE: usize = 20 * 1024 * 1024;
+// Limits for what it unpacks to, so a small archive can't fill the disk.
+pub const MAX_UNPACKED_SIZE: u64 = 100 * 1024 * 1024;
+pub const MAX_ENTRIES: usize = 5000;
+
+// Exports hold the saved content of each file; live edits are included
+// once they have been autosaved.
+fn write_project(zip: &mut ZipWriter<Cursor<Vec<u8>>>, storage: &Storage, room_id: &str, prefix: &str, project: &Project) -> zip::result::ZipResult<()> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for folder in &project.folders {
+        zip.add_directory(format!("{}{}/", prefix, folder), options)?;
+    }
+    for file in &project.files {
+        zip.start_file(format!("{}{}", prefix, file.path()), options)?;
+        if file.binary {
+            zip.write_all(&storage.read_file(room_id, file.id)?)?;
+        } else {
+            zip.write_all(file.content.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn zip_response(bytes: Vec<u8>, name: &str) -> Response {
+    // Keep the header value plain ASCII whatever the project is called.
+    let filename: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || "-_. ".contains(c) { c } else { '_' }).collect();
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.zip\"", filename)),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+// Project names are free text; as a folder name in a room export they must
+// be a single safe path segment.
+fn folder_name(project: &Project) -> String {
+    let name: String = project.name.chars().map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c }).collect();
+    if validate_file_name(&name).is_ok() { name } else { format!("project-{}", project.id) }
+}
+
+// Handler for downloading a project as a ZIP archive
+pub async fn export_project(State(app_state): State<AppState>, user: AuthUser, Path(project_id): Path<i32>) -> Response {
+    info!("[archive] ==> API call to export_project {} by user '{}'", project_id, user.username);
+    let file_system = app_state.file_system.lock().await;
+    let Some(project) = file_system.get(&user.room_id).and_then(|projects| projects.iter().find(|p| p.id == project_id)) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let result = write_project(&mut zip, &app_state.storage, &user.room_id, "", project).and_then(|_| zip.finish());
+    match result {
+        Ok(cursor) => {
+            info!("[archive] <== SUCCESS: Exported project {} ({} files).", project_id, project.files.len());
+            zip_response(cursor.into_inner(), &project.name)
+        }
+        Err(e) => {
+            error!("[archive] <== FAILURE: Could not export project {}: {}", project_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Could not create archive").into_response()
+        }
+    }
+}
+
+// Handler for downloading every project in the caller's room, one folder
+// per project
+pub async fn export_room(State(app_state): State<AppState>, user: AuthUser) -> Response {
+    info!("[archive] ==> API call to export_room '{}' by user '{}'", user.room_id, user.username);
+    let file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Room not found").into_response();
+    };
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let result = projects
+        .iter()
+        .try_for_each(|project| write_project(&mut zip, &app_state.storage, &user.room_id, &format!("{}/", folder_name(project)), project))
+        .and_then(|_| zip.finish());
+    match result {
+        Ok(cursor) => {
+            info!("[archive] <== SUCCESS: Exported room '{}' ({} projects).", user.room_id, projects.len());
+            zip_response(cursor.into_inner(), &user.room_id)
+        }
+        Err(e) => {
+            error!("[archive] <== FAILURE: Could not export room '{}': {}", user.room_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Could not create archive").into_response()
+        }
+    }
+}
+
+// An archive entry that passed validation, path relative to the project root.
+pub enum Entry {
+    Folder(String),
+    File(String, Vec<u8>),
+}
+
+// Splits an entry name into its path segments. Absolute paths, ".." and
+// anything else that could point outside the project are rejected.
+pub fn entry_path(name: &str) -> Result<Vec<&str>, &'static str> {
+    if name.starts_with('/') || name.contains('\\') {
+        return Err("Archive contains an unsafe path");
+    }
+    let segments: Vec<&str> = name.split('/').filter(|s| !s.is_empty()).collect();
+    // A Windows drive, as in "C:/..."
+    if segments.first().is_some_and(|s| s.ends_with(':')) {
+        return Err("Archive contains an unsafe path");
+    }
+    for segment in &segments {
+        validate_file_name(segment).map_err(|_| "Archive contains an unsafe path")?;
+    }
+    Ok(segments)
+}
+
+fn read_archive(bytes: &[u8]) -> Result<Vec<Entry>, &'static str> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|_| "Not a valid ZIP archive")?;
+    if archive.len() > MAX_ENTRIES {
+        return Err("Archive has too many entries");
+    }
+
+    let mut entries = Vec::new();
+    let mut unpacked = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|_| "Not a valid ZIP archive")?;
+        let segments = entry_path(entry.name())?;
+        // Resource forks added by the macOS archiver.
+        if segments.is_empty() || segments[0] == "__MACOSX" {
+            continue;
+        }
+        let path = segments.join("/");
+        if entry.is_dir() {
+            entries.push(Entry::Folder(path));
+            continue;
+        }
+        // The sizes in the archive can't be trusted, so count what is read.
+        let mut content = Vec::new();
+        (&mut entry).take(MAX_UNPACKED_SIZE - unpacked + 1).read_to_end(&mut content).map_err(|_| "Not a valid ZIP archive")?;
+        unpacked += content.len() as u64;
+        if unpacked > MAX_UNPACKED_SIZE {
+            return Err("Archive is too large when unpacked");
+        }
+        entries.push(Entry::File(path, content));
+    }
+    Ok(strip_common_root(entries))
+}
+
+// Archives made by zipping a folder put everything inside that folder;
+// the project should start at its contents.
+fn strip_common_root(entries: Vec<Entry>) -> Vec<Entry> {
+    let top = |path: &str| path.split('/').next().unwrap_or_default().to_string();
+    let root = match entries.first() {
+        Some(Entry::Folder(path) | Entry::File(path, _)) => top(path),
+        None => return entries,
+    };
+    let shared = entries.iter().all(|entry| match entry {
+        Entry::Folder(path) => top(path) == root,
+        Entry::File(path, _) => path.starts_with(&format!("{}/", root)),
+    });
+    if !shared {
+        return entries;
+    }
+    let strip = |path: String| path.split_once('/').map(|(_, rest)| rest.to_string()).unwrap_or_default();
+    entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Entry::Folder(path) => Some(strip(path)).filter(|p| !p.is_empty()).map(Entry::Folder),
+            Entry::File(path, content) => Some(Entry::File(strip(path), content)),
+        })
+        .collect()
+}
+
+// Turns validated entries into a project. Paths must not repeat and a file
+// can't also be a folder.
+fn build_project(name: String, entries: Vec<Entry>) -> Result<(Project, Vec<Vec<u8>>), &'static str> {
+    let mut project = Project { id: 0, name, folders: Vec::new(), files: Vec::new() };
+    let mut bytes = Vec::new();
+    let mut paths = HashSet::new();
+    for entry in entries {
+        match entry {
+            Entry::Folder(path) => project.ensure_folder(&path),
+            Entry::File(path, content) => {
+                if !paths.insert(path.clone()) {
+                    return Err("Archive contains the same path twice");
+                }
+                let folder = state::parent_path(&path).to_string();
+                let name = path.rsplit('/').next().unwrap_or_default().to_string();
+                project.ensure_folder(&folder);
+                let file = match as_text(&content) {
+                    Some(text) => File { id: 0, name, folder, content: text, version: 1, binary: false },
+                    None => File { id: 0, name, folder, content: String::new(), version: 1, binary: true },
+                };
+                project.files.push(file);
+                bytes.push(content);
+            }
+        }
+    }
+    if project.files.iter().any(|f| project.has_folder(&f.path())) {
+        return Err("Archive contains a file and a folder with the same path");
+    }
+    // Ids are handed out only once the archive is known to be good.
+    project.id = state::allocate_project_id();
+    for file in &mut project.files {
+        file.id = state::allocate_file_id();
+    }
+    Ok((project, bytes))
+}
+
+// Handler for creating a project from an uploaded ZIP archive. The form has
+// a `file` part with the archive and an optional `name` for the project,
+// which defaults to the archive's file name.
+pub async fn import_project(State(app_state): State<AppState>, user: AuthUser, mut multipart: Multipart) -> Response {
+    info!("[archive] ==> API call to import_project in room '{}' by user '{}'", user.room_id, user.username);
+    let mut name = None;
+    let mut archive: Option<(String, Bytes)> = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return form_error(e).into_response(),
+        };
+        let result = match field.name() {
+            Some("name") => field.text().await.map(|text| name = Some(text)),
+            Some("file") => {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+                field.bytes().await.map(|bytes| archive = Some((file_name, bytes)))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return form_error(e).into_response();
+        }
+    }
+    let Some((file_name, bytes)) = archive else {
+        return (StatusCode::BAD_REQUEST, "No archive was uploaded").into_response();
+    };
+    let name = name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| {
+        let stem = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+        stem.strip_suffix(".zip").unwrap_or(stem).to_string()
+    });
+    if let Err(message) = validate_project_name(&name) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    // Unpacking is blocking work, done before the file system is locked.
+    let entries = match tokio::task::spawn_blocking(move || read_archive(&bytes)).await {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(message)) => {
+            info!("[archive] <== FAILURE: Rejected archive '{}': {}", file_name, message);
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
+        Err(e) => {
+            error!("[archive] <== FAILURE: Unpacking '{}' was interrupted: {}", file_name, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Import was interrupted").into_response();
+        }
+    };
+    add_project(&app_state, &user, name, entries, &file_name).await
+}
+
+// Creates a project from imported entries, with `source` naming where they
+// came from in the logs.
+pub async fn add_project(app_state: &AppState, user: &AuthUser, name: String, entries: Vec<Entry>, source: &str) -> Response {
+    let mut file_system = app_state.file_system.lock().await;
+    if let Err(e) = state::ensure_room(&mut file_system, &app_state.storage, &user.room_id) {
+        error!("[archive] <== FAILURE: Could not create room '{}': {}", user.room_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Could not save changes").into_response();
+    }
+    let projects = file_system.get_mut(&user.room_id).expect("room was just ensured");
+    if projects.iter().any(|p| p.name == name) {
+        return (StatusCode::CONFLICT, "A project with that name already exists").into_response();
+    }
+    let (project, contents) = match build_project(name, entries) {
+        Ok(built) => built,
+        Err(message) => {
+            info!("[archive] <== FAILURE: Rejected '{}': {}", source, message);
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
+    };
+
+    for (file, content) in project.files.iter().zip(&contents) {
+        if let Err(e) = app_state.storage.save_bytes(&user.room_id, file.id, content) {
+            error!("[archive] <== FAILURE: Could not persist imported file '{}': {}", file.path(), e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Could not save changes").into_response();
+        }
+        if !file.binary {
+            revisions::record_revision(app_state, &user.room_id, file.id, &user.username, &file.content, SaveKind::Explicit);
+        }
+    }
+    let (project_id, summary) = (project.id, ProjectSummary::from(&project));
+    if let Err(status) = update_projects(app_state, &user.room_id, projects, |projects| projects.push(project)) {
+        return (status, "Could not save changes").into_response();
+    }
+    info!("[archive] <== SUCCESS: Imported '{}' as project {}.", source, project_id);
+    (StatusCode::CREATED, Json(summary)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            if name.ends_with('/') {
+                zip.add_directory(*name, SimpleFileOptions::default()).unwrap();
+            } else {
+                zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                zip.write_all(content).unwrap();
+            }
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn paths(entries: &[Entry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Folder(path) | Entry::File(path, _) => path.as_str(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn entry_paths_are_split_into_segments() {
+        assert_eq!(entry_path("css/style.css"), Ok(vec!["css", "style.css"]));
+        assert_eq!(entry_path("assets//img/"), Ok(vec!["assets", "img"]));
+    }
+
+    #[test]
+    fn entry_paths_outside_the_project_are_rejected() {
+        for name in ["../evil", "css/../../evil", "./x", "/etc/passwd", "C:/Windows/x", "dir\\..\\evil", "a/\u{0}b"] {
+            assert!(entry_path(name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn archives_are_read_without_their_common_root() {
+        let bytes = zip(&[("site/", b""), ("site/css/", b""), ("site/index.html", b"hi"), ("site/css/a.css", b"b{}"), ("__MACOSX/site/._index.html", b"")]);
+        let entries = read_archive(&bytes).unwrap();
+        assert_eq!(paths(&entries), ["css", "index.html", "css/a.css"]);
+    }
+
+    #[test]
+    fn archives_with_unsafe_paths_are_rejected() {
+        assert_eq!(read_archive(&zip(&[("ok.txt", b"x"), ("../evil", b"x")])).err(), Some("Archive contains an unsafe path"));
+        assert_eq!(read_archive(b"not a zip").err(), Some("Not a valid ZIP archive"));
+    }
+
+    #[test]
+    fn files_and_folders_cannot_share_a_path() {
+        let entries = vec![Entry::File("a".to_string(), b"x".to_vec()), Entry::Folder("a".to_string())];
+        assert_eq!(build_project("p".to_string(), entries).err(), Some("Archive contains a file and a folder with the same path"));
+        let entries = vec![Entry::File("a".to_string(), b"x".to_vec()), Entry::File("a".to_string(), b"y".to_vec())];
+        assert_eq!(build_project("p".to_string(), entries).err(), Some("Archive contains the same path twice"));
+    }
+}
@@ -0,0 +1,190 @@
+use axum::{
+    body::Bytes,
+    extract::{multipart::MultipartError, Multipart, Path, State},
+    http::{header, StatusCode},
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashSet;
+use tracing::{error, info};
+
+use crate::events::ProjectEvent;
+use crate::files::{authorize_file, find_file, normalize_folder_path, update_projects, validate_file_name, FileSummary};
+use crate::preview::content_type;
+use crate::revisions::{self, SaveKind};
+use crate::session::AuthUser;
+use crate::state::{self, AppState, File};
+
+// Limit for a whole upload request, all files included.
+pub const MAX_UPLOAD_SIZE: usize = 10 * 1024 * 1024;
+
+// The multipart form sent to /api/file/upload:
+//   project_id   the project to upload into
+//   folder       optional folder path, the project root by default
+//   file         one part per file, named by its filename
+struct UploadForm {
+    project_id: i32,
+    folder: String,
+    files: Vec<(String, Bytes)>,
+}
+
+async fn read_form(mut multipart: Multipart) -> Result<UploadForm, (StatusCode, &'static str)> {
+    let mut project_id = None;
+    let mut folder = String::new();
+    let mut files = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(form_error)? {
+        match field.name() {
+            Some("project_id") => {
+                let text = field.text().await.map_err(form_error)?;
+                project_id = Some(text.trim().parse().map_err(|_| (StatusCode::BAD_REQUEST, "Invalid project_id"))?);
+            }
+            Some("folder") => folder = field.text().await.map_err(form_error)?,
+            Some("file") => {
+                let name = field.file_name().unwrap_or_default().to_string();
+                let bytes = field.bytes().await.map_err(form_error)?;
+                files.push((name, bytes));
+            }
+            _ => {}
+        }
+    }
+    let project_id = project_id.ok_or((StatusCode::BAD_REQUEST, "Missing project_id"))?;
+    if files.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No files were uploaded"));
+    }
+    Ok(UploadForm { project_id, folder, files })
+}
+
+pub fn form_error(e: MultipartError) -> (StatusCode, &'static str) {
+    info!("[assets] Could not read upload: {}", e.body_text());
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => (StatusCode::PAYLOAD_TOO_LARGE, "Upload is too large"),
+        _ => (StatusCode::BAD_REQUEST, "Invalid upload"),
+    }
+}
+
+// Valid UTF-8 without NUL bytes is stored as an ordinary text file, so an
+// uploaded stylesheet or SVG can still be edited.
+pub fn as_text(bytes: &[u8]) -> Option<String> {
+    std::str::from_utf8(bytes).ok().filter(|text| !text.contains('\0')).map(str::to_string)
+}
+
+// Handler for uploading one or more files into a project
+pub async fn upload_files(State(app_state): State<AppState>, user: AuthUser, multipart: Multipart) -> Response {
+    info!("[assets] ==> API call to upload_files by user '{}'", user.username);
+    let form = match read_form(multipart).await {
+        Ok(form) => form,
+        Err((status, message)) => return (status, message).into_response(),
+    };
+    let folder = match normalize_folder_path(&form.folder) {
+        Ok(folder) => folder,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let mut names = HashSet::new();
+    for (name, _) in &form.files {
+        if let Err(message) = validate_file_name(name) {
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
+        if !names.insert(name) {
+            return (StatusCode::BAD_REQUEST, "The same file name was uploaded twice").into_response();
+        }
+    }
+
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Room not found").into_response();
+    };
+    let Some(index) = projects.iter().position(|p| p.id == form.project_id) else {
+        info!("[assets] <== FAILURE: Project {} not found in room '{}'.", form.project_id, user.room_id);
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let project = &projects[index];
+    if let Some((name, _)) = form
+        .files
+        .iter()
+        .find(|(name, _)| project.file_at(&folder, name).is_some() || project.has_folder(&state::join_path(&folder, name)))
+    {
+        info!("[assets] <== FAILURE: '{}' already exists in project {}.", name, project.id);
+        return (StatusCode::CONFLICT, "A file with that name already exists").into_response();
+    }
+    if project.folder_blocked(&folder) {
+        info!("[assets] <== FAILURE: A file is in the way of folder '{}' in project {}.", folder, project.id);
+        return (StatusCode::CONFLICT, "A file already exists where the folder would be").into_response();
+    }
+
+    let mut summaries = Vec::new();
+    let mut files = Vec::new();
+    for (name, bytes) in form.files {
+        let id = state::allocate_file_id();
+        let file = match as_text(&bytes) {
+            Some(content) => File { id, name, folder: folder.clone(), content, version: 1, binary: false },
+            None => File { id, name, folder: folder.clone(), content: String::new(), version: 1, binary: true },
+        };
+        if let Err(e) = app_state.storage.save_bytes(&user.room_id, file.id, &bytes) {
+            error!("[assets] <== FAILURE: Could not persist uploaded file '{}': {}", file.name, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Could not save changes").into_response();
+        }
+        // Revisions hold text only.
+        if !file.binary {
+            revisions::record_revision(&app_state, &user.room_id, file.id, &user.username, &file.content, SaveKind::Explicit);
+        }
+        info!("[assets] Stored '{}' ({} bytes, {}).", file.name, bytes.len(), if file.binary { "binary" } else { "text" });
+        summaries.push(FileSummary::new(&file, form.project_id));
+        files.push(file);
+    }
+    let added = update_projects(&app_state, &user.room_id, projects, |projects| {
+        projects[index].files.extend(files);
+        projects[index].ensure_folder(&folder);
+    });
+    if let Err(status) = added {
+        return (status, "Could not save changes").into_response();
+    }
+    app_state.project_events.publish(form.project_id, ProjectEvent::Tree);
+    info!("[assets] <== SUCCESS: Uploaded {} files into project {}.", summaries.len(), form.project_id);
+    (StatusCode::CREATED, Json(summaries)).into_response()
+}
+
+// Handler for downloading a file's saved content, text or binary
+pub async fn download_file(State(app_state): State<AppState>, user: AuthUser, Path(file_id): Path<i32>) -> Response {
+    info!("[assets] ==> API call to download_file for file_id: {} by user '{}'", file_id, user.username);
+    let file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let file = find_file(&file_system, &user.room_id, file_id).expect("authorized file exists");
+    let bytes = if file.binary {
+        match app_state.storage.read_file(&user.room_id, file_id) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("[assets] <== FAILURE: Could not read file {}: {}", file_id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Could not read file").into_response();
+            }
+        }
+    } else {
+        file.content.clone().into_bytes()
+    };
+
+    info!("[assets] <== SUCCESS: Sending '{}' ({} bytes).", file.name, bytes.len());
+    (
+        [
+            (header::CONTENT_TYPE, content_type(&file.name)),
+            (header::CONTENT_DISPOSITION, content_disposition(&file.name)),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+// `filename` is an ASCII fallback; `filename*` carries the real name for
+// clients that understand RFC 5987.
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name.chars().map(|c| if c.is_ascii() && c != '"' && c != '\\' { c } else { '_' }).collect();
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
@@ -1,5 +1,6 @@
 use axum::{
-    http::StatusCode,
+    extract::State,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -8,16 +9,14 @@ use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use once_cell::sync::Lazy;
 use rand_core::OsRng;
-use serde::Deserialize;
-use std::fs::{read_to_string, OpenOptions};
-use std::io::Write;
-use std::sync::Mutex;
-use std::mem::drop;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
 
-static FILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
-const USERS_FILE: &str = "users.txt";
+use crate::projects::validate_room_id;
+use crate::session::session_cookie;
+use crate::state::{self, AppState};
+use crate::users::{StoreError, StoredUser};
 
 #[derive(Deserialize)]
 pub struct AuthData {
@@ -26,25 +25,25 @@ pub struct AuthData {
     pub room_id: String,
 }
 
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub message: &'static str,
+    pub token: String,
+    pub username: String,
+    pub room_id: String,
+    pub expires_at: u64,
+}
+
 fn create_response(status: StatusCode, message: &'static str) -> Response {
     (status, Json(message)).into_response()
 }
 
-pub async fn signup_user(Json(data): Json<AuthData>) -> Response {
-    println!("--- [SIGNUP] New signup request for user '{}' ---", data.username);
-    let _lock = FILE_LOCK.lock().unwrap();
-
-    let existing_data = read_to_string(USERS_FILE).unwrap_or_default();
-    for line in existing_data.lines() {
-        if let Some((stored_username, _, _)) = parse_user_line(line) {
-            if data.username == stored_username {
-                println!("[SIGNUP] Abort: Username '{}' already exists.", data.username);
-                drop(_lock);
-                return create_response(StatusCode::CONFLICT, "Username already exists");
-            }
-        }
+pub async fn signup_user(State(state): State<AppState>, Json(data): Json<AuthData>) -> Response {
+    info!("[auth] ==> Signup request for user '{}'", data.username);
+    if let Err(message) = validate_room_id(&data.room_id) {
+        return create_response(StatusCode::BAD_REQUEST, message);
     }
-    
+
     let salt_pass = SaltString::generate(&mut OsRng);
     let hashed_password = match Argon2::default().hash_password(data.password.as_bytes(), &salt_pass) {
         Ok(h) => h.to_string(),
@@ -57,69 +56,76 @@ pub async fn signup_user(Json(data): Json<AuthData>) -> Response {
         Err(_) => return create_response(StatusCode::INTERNAL_SERVER_ERROR, "Error hashing room ID"),
     };
 
-    match OpenOptions::new().append(true).create(true).open(USERS_FILE) {
-        Ok(mut file) => {
-            let new_line = format!("{},{},{}", data.username, hashed_password, hashed_room_id);
-            if writeln!(file, "{}", new_line).is_err() {
-                return create_response(StatusCode::INTERNAL_SERVER_ERROR, "Error saving user data");
-            }
+    let user = StoredUser {
+        username: data.username.clone(),
+        password_hash: hashed_password,
+        room_id_hash: hashed_room_id,
+    };
+    match state.user_store.insert(&user) {
+        Ok(()) => {}
+        Err(StoreError::Duplicate) => {
+            info!("[auth] <== FAILURE: Username '{}' already exists.", data.username);
+            return create_response(StatusCode::CONFLICT, "Username already exists");
+        }
+        Err(StoreError::InvalidUsername) => {
+            return create_response(StatusCode::BAD_REQUEST, "Invalid username");
+        }
+        Err(e) => {
+            error!("[auth] <== FAILURE: Could not save user '{}': {}", data.username, e);
+            return create_response(StatusCode::INTERNAL_SERVER_ERROR, "Error saving user data");
         }
-        Err(_) => return create_response(StatusCode::INTERNAL_SERVER_ERROR, "Error saving user data"),
     }
-    
-    drop(_lock);
-    println!("[SIGNUP] SUCCESS: Signup completed for user '{}'.", data.username);
-    create_response(StatusCode::CREATED, "User signed up successfully")
-}
 
-pub async fn login_user(Json(data): Json<AuthData>) -> Response {
-    println!("--- [LOGIN] New login request for user: '{}' ---", data.username);
-    let _lock = FILE_LOCK.lock().unwrap();
+    // Signing up with a room id nobody has used yet starts a fresh workspace.
+    let mut file_system = state.file_system.lock().await;
+    match state::ensure_room(&mut file_system, &state.storage, &data.room_id) {
+        Ok(true) => info!("[auth] Created a new room for user '{}'.", data.username),
+        Ok(false) => {}
+        Err(e) => warn!("[auth] Could not create a room for user '{}': {}", data.username, e),
+    }
+    drop(file_system);
 
-    let contents = match read_to_string(USERS_FILE) {
-        Ok(c) => c,
-        Err(_) => return create_response(StatusCode::INTERNAL_SERVER_ERROR, "Error reading user data"),
-    };
+    info!("[auth] <== SUCCESS: Signed up user '{}'.", data.username);
+    create_response(StatusCode::CREATED, "User signed up successfully")
+}
 
-    for line in contents.lines() {
-        if let Some((stored_username, stored_password_hash, stored_room_id_hash)) = parse_user_line(line) {
-            if data.username == stored_username {
-                let is_valid_login = || -> Option<bool> {
-                    let argon2 = Argon2::default();
-                    let pass_hash = PasswordHash::new(stored_password_hash).ok()?;
-                    let pass_ok = argon2.verify_password(data.password.as_bytes(), &pass_hash).is_ok();
-                    let room_hash = PasswordHash::new(stored_room_id_hash).ok()?;
-                    let room_ok = argon2.verify_password(data.room_id.as_bytes(), &room_hash).is_ok();
-                    Some(pass_ok && room_ok)
-                }();
+pub async fn login_user(State(state): State<AppState>, Json(data): Json<AuthData>) -> Response {
+    info!("[auth] ==> Login request for user '{}'", data.username);
 
-                if let Some(true) = is_valid_login {
-                    println!("[LOGIN] SUCCESS: Credentials verified for user '{}'.", stored_username);
-                    drop(_lock);
-                    return create_response(StatusCode::OK, "Login successful");
-                } else {
-                    println!("[LOGIN] FAILED: Invalid credentials for user '{}'.", stored_username);
-                    drop(_lock);
-                    return create_response(StatusCode::UNAUTHORIZED, "Invalid username, password, or room ID");
-                }
-            }
+    let stored = match state.user_store.find(&data.username) {
+        Ok(Some(stored)) => stored,
+        Ok(None) => {
+            info!("[auth] <== FAILURE: User '{}' not found.", data.username);
+            return create_response(StatusCode::UNAUTHORIZED, "Invalid username, password, or room ID");
         }
-    }
+        Err(e) => {
+            error!("[auth] <== FAILURE: Could not look up user '{}': {}", data.username, e);
+            return create_response(StatusCode::INTERNAL_SERVER_ERROR, "Error reading user data");
+        }
+    };
 
-    println!("[LOGIN] FAILED: User '{}' not found.", data.username);
-    drop(_lock);
-    create_response(StatusCode::UNAUTHORIZED, "Invalid username, password, or room ID")
-}
+    let is_valid_login = || -> Option<bool> {
+        let argon2 = Argon2::default();
+        let pass_hash = PasswordHash::new(&stored.password_hash).ok()?;
+        let pass_ok = argon2.verify_password(data.password.as_bytes(), &pass_hash).is_ok();
+        let room_hash = PasswordHash::new(&stored.room_id_hash).ok()?;
+        let room_ok = argon2.verify_password(data.room_id.as_bytes(), &room_hash).is_ok();
+        Some(pass_ok && room_ok)
+    }();
 
-fn parse_user_line(line: &str) -> Option<(&str, &str, &str)> {
-    let (username, rest) = line.split_once(',')?;
-    let separator = ",$argon2id$";
-    
-    if let Some(separator_index) = rest.find(separator) {
-        let pass_hash = &rest[..separator_index];
-        let room_hash = &rest[separator_index + 1..];
-        Some((username, pass_hash, room_hash))
+    if let Some(true) = is_valid_login {
+        info!("[auth] <== SUCCESS: Credentials verified for user '{}'.", stored.username);
+        let (token, claims) = state.sessions.issue(&data.username, &data.room_id);
+        let body = LoginResponse {
+            message: "Login successful",
+            token: token.clone(),
+            username: claims.username,
+            room_id: claims.room_id,
+            expires_at: claims.exp,
+        };
+        (StatusCode::OK, [(header::SET_COOKIE, session_cookie(&token))], Json(body)).into_response()
     } else {
-        None
+        info!("[auth] <== FAILURE: Invalid credentials for user '{}'.", stored.username);
+        create_response(StatusCode::UNAUTHORIZED, "Invalid username, password, or room ID")
     }
-}
\ No newline at end of file
+}
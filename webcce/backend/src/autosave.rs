@@ -0,0 +1,160 @@
+use axum::http::StatusCode;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::files::{find_file, store_file_content};
+use crate::protocol::ServerMessage;
+use crate::revisions::SaveKind;
+use crate::state::{AppState, Project, Room};
+use crate::ws::send_message;
+
+// Live documents are written back to their file once nobody has edited them
+// for AUTOSAVE_DELAY, and at the latest AUTOSAVE_MAX_DELAY after the first
+// unsaved edit, so a busy file still gets saved regularly.
+const AUTOSAVE_DELAY: Duration = Duration::from_secs(2);
+const AUTOSAVE_MAX_DELAY: Duration = Duration::from_secs(30);
+const TICK: Duration = Duration::from_secs(1);
+
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            for file_id in due_files(&state).await {
+                if let Err(status) = flush(&state, file_id, None).await {
+                    warn!("[autosave] Could not save file {}: {}", file_id, status);
+                }
+            }
+        }
+    });
+}
+
+fn is_due(room: &Room, now: Instant) -> bool {
+    let (Some(unsaved_since), Some(last_edit)) = (room.unsaved_since, room.last_edit) else { return false };
+    room.is_dirty()
+        && (now.duration_since(last_edit) >= AUTOSAVE_DELAY || now.duration_since(unsaved_since) >= AUTOSAVE_MAX_DELAY)
+}
+
+async fn due_files(state: &AppState) -> Vec<i32> {
+    let now = Instant::now();
+    let room_manager = state.room_manager.lock().await;
+    room_manager.iter().filter(|(_, room)| is_due(room, now)).map(|(file_id, _)| *file_id).collect()
+}
+
+// Writes the live document of `file_id` back to its file if it has unsaved
+// edits, crediting the revision to everyone who edited since the last save.
+// Then tells everyone in the room which revision is saved. `saved_by` is the
+// user who asked for the save, or None for autosaves; an explicit save is
+// acknowledged even when there was nothing new to write.
+pub async fn flush(state: &AppState, file_id: i32, saved_by: Option<&str>) -> Result<(), StatusCode> {
+    // Lock order: the file system first, then the room manager. The file
+    // system stays locked until the write is done, so nobody can load the
+    // old content in the meantime; the room manager is released so edits
+    // keep flowing.
+    let mut file_system = state.file_system.lock().await;
+    let (room_id, revision, unsaved) = {
+        let room_manager = state.room_manager.lock().await;
+        let Some(room) = room_manager.get(&file_id) else { return Ok(()) };
+        let unsaved = room.is_dirty().then(|| Unsaved {
+            content: room.document.content.clone(),
+            author: room.unsaved_editors.join(", "),
+            based_on: room.file_version,
+        });
+        (room.room_id.clone(), room.document.revision(), unsaved)
+    };
+    if unsaved.is_none() && saved_by.is_none() {
+        return Ok(());
+    }
+    let written = match unsaved {
+        Some(unsaved) => {
+            let kind = if saved_by.is_some() { SaveKind::Explicit } else { SaveKind::Autosave };
+            write_back(state, &mut file_system, &room_id, file_id, unsaved, kind)
+        }
+        None => Ok(None),
+    };
+
+    let mut room_manager = state.room_manager.lock().await;
+    let Some(room) = room_manager.get_mut(&file_id) else { return written.map(|_| ()) };
+    match written {
+        Ok(version) => {
+            if let Some(version) = version {
+                room.file_version = version;
+            }
+            room.mark_saved(revision);
+            for user in room.connections.values() {
+                send_message(&user.sender, &ServerMessage::Saved { revision, username: saved_by });
+            }
+            Ok(())
+        }
+        Err(StatusCode::CONFLICT) => {
+            // Retrying can't help, so autosave waits for the next edit.
+            room.unsaved_since = None;
+            room.last_edit = None;
+            Err(StatusCode::CONFLICT)
+        }
+        Err(status) => Err(status),
+    }
+}
+
+// Called when a connection closes: if it was the last one in the room, the
+// room is dropped and any unsaved edits are written back.
+pub async fn close_room(state: &AppState, file_id: i32) {
+    let mut file_system = state.file_system.lock().await;
+    let room = {
+        let mut room_manager = state.room_manager.lock().await;
+        match room_manager.get(&file_id) {
+            Some(room) if room.connections.is_empty() => room_manager.remove(&file_id),
+            _ => None,
+        }
+    };
+    let Some(room) = room.filter(|room| room.is_dirty()) else { return };
+    let unsaved = Unsaved {
+        author: room.unsaved_editors.join(", "),
+        based_on: room.file_version,
+        content: room.document.content,
+    };
+    let written = write_back(state, &mut file_system, &room.room_id, file_id, unsaved, SaveKind::Autosave);
+    if let Err(status) = written {
+        warn!("[autosave] Could not save file {} after the last user left: {}", file_id, status);
+    }
+}
+
+// A live document's edits that haven't been written back yet: the content,
+// everyone who edited since the last save, and the file version it is based on.
+struct Unsaved {
+    content: String,
+    author: String,
+    based_on: u64,
+}
+
+// Returns the file's new version, or None if there was no file to save into.
+// Refuses with CONFLICT if the file moved past `based_on` behind the room's
+// back, rather than overwriting the newer content.
+fn write_back(
+    state: &AppState,
+    file_system: &mut HashMap<String, Vec<Project>>,
+    room_id: &str,
+    file_id: i32,
+    unsaved: Unsaved,
+    kind: SaveKind,
+) -> Result<Option<u64>, StatusCode> {
+    let Unsaved { content, author, based_on } = unsaved;
+    // The file was deleted while it was open; there is nothing to save into.
+    let Some(file) = find_file(file_system, room_id, file_id) else { return Ok(None) };
+    if file.version != based_on {
+        warn!(
+            "[autosave] Not saving file {} in room '{}': it is at version {}, the live document is based on {}.",
+            file_id, room_id, file.version, based_on
+        );
+        return Err(StatusCode::CONFLICT);
+    }
+    match store_file_content(state, file_system, room_id, file_id, content, &author, kind) {
+        Ok(()) => {
+            info!("[autosave] Saved file {} in room '{}'.", file_id, room_id);
+            Ok(find_file(file_system, room_id, file_id).map(|f| f.version))
+        }
+        Err(StatusCode::NOT_FOUND) => Ok(None),
+        Err(status) => Err(status),
+    }
+}
@@ -1,20 +1,42 @@
-use axum::{extract::State, Json};
-use reqwest::StatusCode;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
-use tracing::{info, warn, error};
+use std::convert::Infallible;
+use std::fmt;
+use std::io;
+use tokio::sync::OwnedMutexGuard;
+use tracing::{info, warn};
 
+use crate::conversations::{self, validate_conversation_id};
+use crate::llm::{Deltas, ProviderError};
+use crate::session::{now_secs, AuthUser};
 use crate::AppState;
 
 #[derive(Deserialize)]
 pub struct ChatRequest {
     pub conversation_id: String,
-    pub messages: Vec<ChatMessage>,
+    // The user's new message. Earlier turns are kept on the server.
+    pub message: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+impl ChatMessage {
+    fn new(role: &str, content: String) -> Self {
+        ChatMessage { role: role.to_string(), content, timestamp: now_secs() }
+    }
 }
 
 #[derive(Serialize)]
@@ -22,137 +44,308 @@ pub struct ChatResponse {
     pub reply: String,
 }
 
-#[derive(Serialize)]
-struct OaIMsg<'a> {
-    role: &'a str,
-    content: &'a str,
+// Why a chat request failed. The browser only gets a status and a message
+// that is safe to show; what actually went wrong is logged.
+#[derive(Debug)]
+pub enum ChatError {
+    // Something is wrong with what the browser sent.
+    InvalidRequest(&'static str),
+    // The provider failed or could not be reached.
+    Provider(ProviderError),
+    // The conversation could not be read or saved.
+    Storage(io::Error),
 }
 
-#[derive(Serialize)]
-struct OaiReq<'a> {
-    model: &'a str,
-    messages: Vec<OaIMsg<'a>>,
-    max_tokens: Option<u32>,
+impl ChatError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ChatError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ChatError::Provider(ProviderError::Unreachable(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            ChatError::Provider(ProviderError::Status { status: 429, .. }) => StatusCode::TOO_MANY_REQUESTS,
+            ChatError::Provider(_) => StatusCode::BAD_GATEWAY,
+            ChatError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            ChatError::InvalidRequest(message) => message,
+            ChatError::Provider(ProviderError::Unreachable(_)) => "The assistant is unavailable right now",
+            ChatError::Provider(ProviderError::Status { status: 429, .. }) => "The assistant is busy, try again in a moment",
+            ChatError::Provider(ProviderError::Status { .. }) => "The assistant could not answer",
+            ChatError::Provider(ProviderError::InvalidResponse(_)) => "The assistant sent an invalid reply",
+            ChatError::Provider(ProviderError::CutOff) => "The reply was cut off",
+            ChatError::Storage(_) => "Could not read or save the conversation",
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct OaiChoice {
-    message: Option<OaiMessage>,
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            ChatError::Provider(e) => e.fmt(f),
+            ChatError::Storage(e) => write!(f, "conversation storage error: {}", e),
+        }
+    }
 }
-#[derive(Deserialize)]
-struct OaiMessage {
-    content: Option<String>,
+
+impl From<ProviderError> for ChatError {
+    fn from(e: ProviderError) -> Self {
+        ChatError::Provider(e)
+    }
 }
-#[derive(Deserialize)]
-struct OaiResp {
-    choices: Vec<OaiChoice>,
+
+impl IntoResponse for ChatError {
+    fn into_response(self) -> Response {
+        (self.status(), self.message()).into_response()
+    }
+}
+
+impl From<io::Error> for ChatError {
+    fn from(e: io::Error) -> Self {
+        ChatError::Storage(e)
+    }
 }
 
-fn headers_to_string(hdrs: &reqwest::header::HeaderMap) -> String {
-    hdrs.iter()
-        .map(|(k, v)| {
-            let v = v.to_str().unwrap_or("<non-utf8>");
-            format!("{}: {}", k.as_str(), v)
-        })
-        .collect::<Vec<_>>()
-        .join("\n")
+// Checks the request, waits for any other turn in the conversation to finish,
+// and returns the recent history with the new message added, ready for the
+// provider. The turn lasts until the returned guard is dropped.
+async fn start_turn(
+    state: &AppState,
+    username: &str,
+    payload: &ChatRequest,
+) -> Result<(OwnedMutexGuard<()>, Vec<ChatMessage>), ChatError> {
+    validate_conversation_id(&payload.conversation_id).map_err(ChatError::InvalidRequest)?;
+    if payload.message.trim().is_empty() {
+        return Err(ChatError::InvalidRequest("There is nothing to reply to"));
+    }
+    let turn = conversations::lock_turn(state, username, &payload.conversation_id).await;
+    let mut messages = conversations::history(state, username, &payload.conversation_id).await?;
+    messages.push(ChatMessage::new("user", payload.message.clone()));
+    Ok((turn, messages))
+}
+
+// Saves the question and its reply. Nothing is saved for a failed reply, so
+// the user can simply ask again.
+async fn finish_turn(state: &AppState, username: &str, conversation_id: &str, question: ChatMessage, reply: &str) -> Result<(), ChatError> {
+    let turn = vec![question, ChatMessage::new("assistant", reply.to_string())];
+    Ok(conversations::append(state, username, conversation_id, turn).await?)
 }
 
 pub async fn handle_chat(
     State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<ChatRequest>,
+) -> Result<Json<ChatResponse>, ChatError> {
+    info!("[chat] ==> Chat request from user '{}' in conversation '{}'.", user.username, payload.conversation_id);
+    let result = async {
+        let (_turn, mut messages) = start_turn(&state, &user.username, &payload).await?;
+        let reply = state.chat_provider.complete(&messages).await?;
+        let question = messages.pop().expect("start_turn adds the question");
+        finish_turn(&state, &user.username, &payload.conversation_id, question, &reply).await?;
+        Ok(reply)
+    }
+    .await;
+    match result {
+        Ok(reply) => {
+            info!("[chat] <== SUCCESS: Replied to '{}' with {} bytes.", user.username, reply.len());
+            Ok(Json(ChatResponse { reply }))
+        }
+        Err(e) => {
+            warn!("[chat] <== FAILURE: Chat request from '{}' failed: {}", user.username, e);
+            Err(e)
+        }
+    }
+}
+
+// What the browser receives from /chat/stream, as server-sent events named
+// after the variant. The stream ends after `done` or `error`.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ChatEvent {
+    // The next piece of the reply.
+    Delta { content: String },
+    // The reply is complete; `reply` is all the deltas joined.
+    Done { reply: String },
+    // The reply stopped part way. The message is safe to show; details are
+    // only logged.
+    Error { message: &'static str },
+}
+
+impl ChatEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            ChatEvent::Delta { .. } => "delta",
+            ChatEvent::Done { .. } => "done",
+            ChatEvent::Error { .. } => "error",
+        }
+    }
+}
+
+// Handler for streaming a reply token by token. Failures before the first
+// byte are plain HTTP errors; later ones arrive as an `error` event.
+pub async fn handle_chat_stream(
+    State(state): State<AppState>,
+    user: AuthUser,
     Json(payload): Json<ChatRequest>,
-) -> Json<ChatResponse> {
-    // Build OA-compatible messages
-    let oa_msgs: Vec<OaIMsg> = payload
-        .messages
-        .iter()
-        .map(|m| OaIMsg { role: &m.role, content: &m.content })
-        .collect();
-
-    // Keep the requested model
-    let req_body = OaiReq {
-        model: "gpt-5-nano",
-        messages: oa_msgs,
-        max_tokens: Some(800),
+) -> Response {
+    info!("[chat] ==> Streaming chat request from user '{}' in conversation '{}'.", user.username, payload.conversation_id);
+
+    let result = async {
+        let (turn, messages) = start_turn(&state, &user.username, &payload).await?;
+        let deltas = state.chat_provider.stream(&messages).await?;
+        Ok::<_, ChatError>((turn, messages, deltas))
+    }
+    .await;
+    let (turn, mut messages, deltas) = match result {
+        Ok(started) => started,
+        Err(e) => {
+            warn!("[chat] <== FAILURE: Streaming chat request from '{}' failed: {}", user.username, e);
+            return e.into_response();
+        }
     };
 
-    // Serialize body for logs
-    let body_json = serde_json::to_string_pretty(&req_body).unwrap_or_else(|_| "(serialize error)".into());
-
-    // Log presence of key but do NOT print it
-    info!("Preparing OpenAI request. key_present={}", !state.openai_api_key.is_empty());
-    info!("OpenAI request URL: https://api.openai.com/v1/chat/completions");
-    info!("OpenAI request body: {}", body_json);
-
-    let client = reqwest::Client::new();
-
-    // Send request and capture result
-    let send_result = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(&state.openai_api_key) // reqwest sets Authorization header internally
-        .json(&req_body)
-        .send()
-        .await;
-
-    // Build debug reply that we will return to caller when something goes wrong.
-    let mut debug_parts: Vec<String> = Vec::new();
-    debug_parts.push("=== DEBUG: OpenAI request ===".to_string());
-    debug_parts.push("URL: https://api.openai.com/v1/chat/completions".to_string());
-    debug_parts.push(format!("key_present: {}", !state.openai_api_key.is_empty()));
-    debug_parts.push("REQUEST BODY:".to_string());
-    debug_parts.push(body_json);
-
-    // Evaluate response
-    let final_reply = match send_result {
-        Ok(resp) if resp.status().is_success() => {
-            // success path: parse a normal OpenAI response
-            match resp.json::<OaiResp>().await {
-                Ok(parsed) => {
-                    parsed
-                        .choices
-                        .into_iter()
-                        .next()
-                        .and_then(|c| c.message.and_then(|m| m.content))
-                        .unwrap_or_else(|| "(no reply)".to_string())
-                }
-                Err(e) => {
-                    warn!("OpenAI parse error: {}", e);
-                    debug_parts.push(format!("ERROR parsing OpenAI JSON: {}", e));
-                    // try to extract raw text too
-                    let raw = resp.text().await.unwrap_or_else(|_| "(no body)".to_string());
-                    debug_parts.push("RAW BODY:".to_string());
-                    debug_parts.push(raw);
-                    debug_parts.join("\n\n")
+    let relay = Relay {
+        deltas,
+        reply: String::new(),
+        state,
+        username: user.username,
+        conversation_id: payload.conversation_id,
+        question: messages.pop().expect("start_turn adds the question"),
+        turn,
+    };
+    // When the browser goes away the stream, and with it the deltas, is
+    // dropped, which stops the provider too.
+    Sse::new(chat_stream(relay)).keep_alive(KeepAlive::default()).into_response()
+}
+
+// A reply being streamed, and what is needed to save it once it is complete.
+struct Relay {
+    deltas: Deltas,
+    reply: String,
+    state: AppState,
+    username: String,
+    conversation_id: String,
+    question: ChatMessage,
+    // Held until the reply is saved or the stream is dropped.
+    turn: OwnedMutexGuard<()>,
+}
+
+// Turns the provider's deltas into `delta` events, ending with `done` once the
+// reply is complete and saved, or `error` if it broke off.
+fn chat_stream(relay: Relay) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(Some(relay), |relay| async move {
+        let mut relay = relay?;
+        let (event, relay) = match relay.deltas.recv().await {
+            Some(Ok(content)) => {
+                relay.reply.push_str(&content);
+                (ChatEvent::Delta { content }, Some(relay))
+            }
+            Some(Err(e)) => {
+                warn!("[chat] <== FAILURE: Streaming to '{}' failed: {}", relay.username, e);
+                (ChatEvent::Error { message: ChatError::from(e).message() }, None)
+            }
+            None => {
+                let Relay { reply, state, username, conversation_id, question, turn, .. } = relay;
+                let saved = finish_turn(&state, &username, &conversation_id, question, &reply).await;
+                drop(turn);
+                match saved {
+                    Ok(()) => {
+                        info!("[chat] <== SUCCESS: Streamed {} bytes to '{}'.", reply.len(), username);
+                        (ChatEvent::Done { reply }, None)
+                    }
+                    Err(e) => {
+                        warn!("[chat] <== FAILURE: Could not save the reply to '{}': {}", username, e);
+                        (ChatEvent::Error { message: e.message() }, None)
+                    }
                 }
             }
-        }
+        };
+        let sse = Event::default().event(event.name()).json_data(&event).unwrap_or_default();
+        Some((Ok(sse), relay))
+    })
+}
 
-        Ok(mut resp) => {
-            // non-success status. Capture headers and body. Return detailed debug.
-            let status = resp.status();
-            let headers = headers_to_string(resp.headers());
-            let body_text = resp.text().await.unwrap_or_else(|_| "(no body)".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ProjectEvents;
+    use crate::llm::MockProvider;
+    use crate::session::SessionKeys;
+    use crate::storage::Storage;
+    use crate::users::FileUserStore;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
 
-            warn!("OpenAI returned status {}. Body: {}", status, body_text);
+    fn test_state(name: &str) -> AppState {
+        let dir = std::env::temp_dir().join(format!("webcce-chat-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        AppState {
+            file_system: Arc::new(Mutex::new(HashMap::new())),
+            room_manager: Arc::new(Mutex::new(HashMap::new())),
+            chat_provider: Arc::new(MockProvider),
+            conversations: Arc::new(Mutex::new(HashMap::new())),
+            conversation_turns: Arc::new(Mutex::new(HashMap::new())),
+            sessions: SessionKeys::from_env(),
+            user_store: Arc::new(FileUserStore::new(dir.join("users.txt"))),
+            storage: Arc::new(Storage::new(dir)),
+            project_events: ProjectEvents::default(),
+        }
+    }
 
-            debug_parts.push("=== OPENAI RESPONSE ===".to_string());
-            debug_parts.push(format!("status: {}", status));
-            debug_parts.push("response headers:".to_string());
-            debug_parts.push(headers);
-            debug_parts.push("response body:".to_string());
-            debug_parts.push(body_text);
+    // Streams a reply to `message` and returns the events as (name, data).
+    async fn stream_reply(state: &AppState, message: &str) -> Vec<(String, serde_json::Value)> {
+        let question = ChatMessage::new("user", message.to_string());
+        let deltas = state.chat_provider.stream(std::slice::from_ref(&question)).await.unwrap();
+        let relay = Relay {
+            deltas,
+            reply: String::new(),
+            state: state.clone(),
+            username: "alice".to_string(),
+            conversation_id: "c1".to_string(),
+            question,
+            turn: conversations::lock_turn(state, "alice", "c1").await,
+        };
+        let body = Sse::new(chat_stream(relay)).into_response().into_body();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .split("\n\n")
+            .filter(|event| !event.is_empty())
+            .map(|event| {
+                let field = |name: &str| event.lines().find_map(|line| line.strip_prefix(name)).unwrap_or_default().to_string();
+                (field("event: "), serde_json::from_str(&field("data: ")).unwrap())
+            })
+            .collect()
+    }
 
-            // return the debug text so you see exact server-side response
-            debug_parts.join("\n\n")
-        }
+    #[tokio::test]
+    async fn stream_sends_deltas_then_done_and_saves_the_turn() {
+        let state = test_state("done");
+        let events = stream_reply(&state, "hello world").await;
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["delta", "delta", "delta", "delta", "done"]);
+        let deltas: String = events[..4].iter().map(|(_, data)| data["content"].as_str().unwrap()).collect();
+        assert_eq!(deltas, "You said: hello world");
+        assert_eq!(events[4].1["reply"], "You said: hello world");
 
-        Err(e) => {
-            // network error / client error
-            error!("Network error calling OpenAI: {}", e);
-            debug_parts.push(format!("NETWORK ERROR: {}", e));
-            debug_parts.join("\n\n")
-        }
-    };
+        let history = conversations::history(&state, "alice", "c1").await.unwrap();
+        let contents: Vec<&str> = history.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["hello world", "You said: hello world"]);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_with_an_error_when_cut_off() {
+        let state = test_state("cutoff");
+        let events = stream_reply(&state, "mock:cutoff").await;
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["delta", "error"]);
+        assert_eq!(events[0].1["content"], "You ");
+        assert!(events[1].1["message"].is_string());
 
-    Json(ChatResponse { reply: final_reply })
+        // A broken reply isn't saved.
+        assert!(conversations::history(&state, "alice", "c1").await.unwrap().is_empty());
+    }
 }
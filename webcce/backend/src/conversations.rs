@@ -0,0 +1,244 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use tokio::sync::OwnedMutexGuard;
+use tracing::{error, info};
+
+use crate::chat::ChatMessage;
+use crate::session::{now_secs, AuthUser};
+use crate::state::AppState;
+use crate::storage::Storage;
+
+// Longest title taken from a conversation's first message.
+const TITLE_LENGTH: usize = 60;
+// How much of a conversation is sent to the provider with a new message:
+// the most recent messages, up to this many and this many bytes.
+const HISTORY_MESSAGES: usize = 40;
+const HISTORY_BYTES: usize = 32 * 1024;
+
+// A chat with the assistant. Conversations belong to a user, not a room, and
+// their ids are picked by the client.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<ChatMessage>,
+}
+
+// A conversation without its messages, for listings
+#[derive(Serialize)]
+pub struct ConversationSummary {
+    id: String,
+    title: String,
+    created_at: u64,
+    updated_at: u64,
+    message_count: usize,
+}
+
+impl From<&Conversation> for ConversationSummary {
+    fn from(conversation: &Conversation) -> Self {
+        ConversationSummary {
+            id: conversation.id.clone(),
+            title: conversation.title.clone(),
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+            message_count: conversation.messages.len(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenameConversationRequest {
+    id: String,
+    title: String,
+}
+
+// Ids end up in file names, so they are kept to a safe alphabet.
+pub fn validate_conversation_id(id: &str) -> Result<(), &'static str> {
+    if id.is_empty() || id.len() > 64 {
+        return Err("Conversation ID must be 1 to 64 characters long");
+    }
+    if !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-') {
+        return Err("Conversation ID may only contain letters, digits, '_' and '-'");
+    }
+    Ok(())
+}
+
+fn validate_title(title: &str) -> Result<(), &'static str> {
+    if title.trim().is_empty() {
+        return Err("Title cannot be empty");
+    }
+    if title.chars().count() > 100 {
+        return Err("Title is too long");
+    }
+    Ok(())
+}
+
+// The first line of the opening message, shortened to fit a sidebar.
+fn title_from(content: &str) -> String {
+    let line = content.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("New conversation");
+    match line.char_indices().nth(TITLE_LENGTH) {
+        Some((end, _)) => format!("{}...", line[..end].trim_end()),
+        None => line.to_string(),
+    }
+}
+
+// Every user's conversations are read from disk the first time they are needed.
+fn user_conversations<'a>(
+    conversations: &'a mut HashMap<String, Vec<Conversation>>,
+    storage: &Storage,
+    username: &str,
+) -> io::Result<&'a mut Vec<Conversation>> {
+    if !conversations.contains_key(username) {
+        let loaded = storage.load_conversations(username)?;
+        conversations.insert(username.to_string(), loaded);
+    }
+    Ok(conversations.get_mut(username).expect("conversations were just loaded"))
+}
+
+// Waits until no other turn is running in the conversation. The turn runs
+// until the guard is dropped.
+pub async fn lock_turn(app_state: &AppState, username: &str, id: &str) -> OwnedMutexGuard<()> {
+    let lock = {
+        let mut turns = app_state.conversation_turns.lock().await;
+        turns.entry((username.to_string(), id.to_string())).or_default().clone()
+    };
+    lock.lock_owned().await
+}
+
+// The recent messages to answer from, or none for a conversation that doesn't
+// exist yet. Older messages are left out once the history gets long, and it
+// always starts with one of the user's messages.
+pub async fn history(app_state: &AppState, username: &str, id: &str) -> io::Result<Vec<ChatMessage>> {
+    let mut conversations = app_state.conversations.lock().await;
+    let list = user_conversations(&mut conversations, &app_state.storage, username)?;
+    let Some(conversation) = list.iter().find(|c| c.id == id) else { return Ok(Vec::new()) };
+    let mut start = conversation.messages.len();
+    let mut bytes = 0;
+    for message in conversation.messages.iter().rev().take(HISTORY_MESSAGES) {
+        bytes += message.content.len();
+        if bytes > HISTORY_BYTES {
+            break;
+        }
+        start -= 1;
+    }
+    let recent = &conversation.messages[start..];
+    let first_question = recent.iter().position(|m| m.role == "user").unwrap_or(recent.len());
+    Ok(recent[first_question..].to_vec())
+}
+
+// Adds a finished turn to the conversation, starting it if this is its first.
+pub async fn append(app_state: &AppState, username: &str, id: &str, turn: Vec<ChatMessage>) -> io::Result<()> {
+    let mut conversations = app_state.conversations.lock().await;
+    let list = user_conversations(&mut conversations, &app_state.storage, username)?;
+    let now = now_secs();
+    let index = match list.iter().position(|c| c.id == id) {
+        Some(index) => index,
+        None => {
+            let title = title_from(turn.first().map(|m| m.content.as_str()).unwrap_or_default());
+            list.push(Conversation { id: id.to_string(), title, created_at: now, updated_at: now, messages: Vec::new() });
+            list.len() - 1
+        }
+    };
+    let mut conversation = list[index].clone();
+    conversation.messages.extend(turn);
+    conversation.updated_at = now;
+    // Only kept in memory once it is safely on disk.
+    app_state.storage.save_conversation(username, &conversation)?;
+    list[index] = conversation;
+    Ok(())
+}
+
+fn storage_failure(e: io::Error) -> Response {
+    error!("[conversations] <== FAILURE: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Could not read or save conversations").into_response()
+}
+
+// Handler for listing the user's conversations, most recently active first
+pub async fn list_conversations(State(app_state): State<AppState>, user: AuthUser) -> Response {
+    info!("[conversations] ==> API call to list_conversations by user '{}'", user.username);
+    let mut conversations = app_state.conversations.lock().await;
+    let list = match user_conversations(&mut conversations, &app_state.storage, &user.username) {
+        Ok(list) => list,
+        Err(e) => return storage_failure(e),
+    };
+    let mut summaries: Vec<ConversationSummary> = list.iter().map(ConversationSummary::from).collect();
+    summaries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| b.created_at.cmp(&a.created_at)));
+    info!("[conversations] <== SUCCESS: {} conversations.", summaries.len());
+    Json(summaries).into_response()
+}
+
+pub async fn get_conversation(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Response {
+    info!("[conversations] ==> API call to get_conversation '{}' by user '{}'", id, user.username);
+    let mut conversations = app_state.conversations.lock().await;
+    let list = match user_conversations(&mut conversations, &app_state.storage, &user.username) {
+        Ok(list) => list,
+        Err(e) => return storage_failure(e),
+    };
+    match list.iter().find(|c| c.id == id) {
+        Some(conversation) => Json(conversation).into_response(),
+        None => (StatusCode::NOT_FOUND, "Conversation not found").into_response(),
+    }
+}
+
+pub async fn rename_conversation(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<RenameConversationRequest>,
+) -> Response {
+    info!("[conversations] ==> API call to rename_conversation '{}' to '{}' by user '{}'", payload.id, payload.title, user.username);
+    if let Err(message) = validate_title(&payload.title) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let mut conversations = app_state.conversations.lock().await;
+    let list = match user_conversations(&mut conversations, &app_state.storage, &user.username) {
+        Ok(list) => list,
+        Err(e) => return storage_failure(e),
+    };
+    let Some(conversation) = list.iter_mut().find(|c| c.id == payload.id) else {
+        return (StatusCode::NOT_FOUND, "Conversation not found").into_response();
+    };
+    let old_title = std::mem::replace(&mut conversation.title, payload.title.trim().to_string());
+    if let Err(e) = app_state.storage.save_conversation(&user.username, conversation) {
+        conversation.title = old_title;
+        return storage_failure(e);
+    }
+    info!("[conversations] <== SUCCESS: Renamed conversation '{}'.", payload.id);
+    Json(ConversationSummary::from(&*conversation)).into_response()
+}
+
+pub async fn delete_conversation(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Response {
+    info!("[conversations] ==> API call to delete_conversation '{}' by user '{}'", id, user.username);
+    let mut conversations = app_state.conversations.lock().await;
+    let list = match user_conversations(&mut conversations, &app_state.storage, &user.username) {
+        Ok(list) => list,
+        Err(e) => return storage_failure(e),
+    };
+    let Some(index) = list.iter().position(|c| c.id == id) else {
+        return (StatusCode::NOT_FOUND, "Conversation not found").into_response();
+    };
+    if let Err(e) = app_state.storage.delete_conversation(&user.username, &id) {
+        return storage_failure(e);
+    }
+    list.remove(index);
+    drop(conversations);
+    app_state.conversation_turns.lock().await.remove(&(user.username.clone(), id.clone()));
+    info!("[conversations] <== SUCCESS: Deleted conversation '{}'.", id);
+    StatusCode::NO_CONTENT.into_response()
+}
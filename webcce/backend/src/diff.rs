@@ -0,0 +1,135 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+use tracing::{error, info};
+
+use crate::files::{authorize_file, find_file};
+use crate::session::AuthUser;
+use crate::state::AppState;
+
+const CONTEXT_LINES: usize = 3;
+
+// Both fields are optional, so `{}` compares the last saved content with the
+// live document (which is the saved content when nobody has the file open).
+#[derive(Deserialize)]
+pub struct DiffRequest {
+    // Compare from this revision instead of the last saved content.
+    #[serde(default)]
+    base_revision: Option<u32>,
+    // Compare against this text instead of the live document.
+    #[serde(default)]
+    draft: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DiffResponse {
+    file_id: i32,
+    base_revision: Option<u32>,
+    // "live" or "draft"
+    target: &'static str,
+    unified: String,
+    hunks: Vec<Hunk>,
+}
+
+// Line numbers are 1-based, as in the unified diff header.
+#[derive(Serialize)]
+pub struct Hunk {
+    old_start: usize,
+    old_lines: usize,
+    new_start: usize,
+    new_lines: usize,
+    lines: Vec<HunkLine>,
+}
+
+#[derive(Serialize)]
+pub struct HunkLine {
+    tag: &'static str,
+    old_line: Option<usize>,
+    new_line: Option<usize>,
+    content: String,
+}
+
+fn build_diff(old: &str, new: &str, path: &str) -> (String, Vec<Hunk>) {
+    let diff = TextDiff::from_lines(old, new);
+    let mut unified = diff.unified_diff();
+    unified.context_radius(CONTEXT_LINES).header(&format!("a/{}", path), &format!("b/{}", path));
+
+    let hunks = unified
+        .iter_hunks()
+        .map(|hunk| {
+            let (first, last) = (hunk.ops().first(), hunk.ops().last());
+            let old_range = first.map_or(0, |op| op.old_range().start)..last.map_or(0, |op| op.old_range().end);
+            let new_range = first.map_or(0, |op| op.new_range().start)..last.map_or(0, |op| op.new_range().end);
+            let lines = hunk
+                .iter_changes()
+                .map(|change| HunkLine {
+                    tag: match change.tag() {
+                        ChangeTag::Equal => "equal",
+                        ChangeTag::Delete => "delete",
+                        ChangeTag::Insert => "insert",
+                    },
+                    old_line: change.old_index().map(|i| i + 1),
+                    new_line: change.new_index().map(|i| i + 1),
+                    content: change.value().to_string(),
+                })
+                .collect();
+            Hunk {
+                old_start: old_range.start + 1,
+                old_lines: old_range.len(),
+                new_start: new_range.start + 1,
+                new_lines: new_range.len(),
+                lines,
+            }
+        })
+        .collect();
+    (unified.to_string(), hunks)
+}
+
+pub async fn diff_file(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(file_id): Path<i32>,
+    Json(payload): Json<DiffRequest>,
+) -> Response {
+    info!("[diff] ==> API call to diff_file for file_id: {} by user '{}'", file_id, user.username);
+    // Lock order: the file system first, then the room manager.
+    let file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let file = find_file(&file_system, &user.room_id, file_id).expect("authorized file exists");
+    if file.binary {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Binary files cannot be diffed").into_response();
+    }
+
+    let base = match payload.base_revision {
+        None => file.content.clone(),
+        Some(revision_id) => match app_state.storage.load_revisions(&user.room_id, file_id) {
+            Ok(history) => match history.into_iter().find(|r| r.id == revision_id) {
+                Some(revision) => revision.content,
+                None => return (StatusCode::NOT_FOUND, "Revision not found").into_response(),
+            },
+            Err(e) => {
+                error!("[diff] <== FAILURE: Could not read history of file {}: {}", file_id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Could not read revisions").into_response();
+            }
+        },
+    };
+    let (target, new) = match payload.draft {
+        Some(draft) => ("draft", draft),
+        None => {
+            let room_manager = app_state.room_manager.lock().await;
+            let live = room_manager.get(&file_id).map(|room| room.document.content.clone());
+            ("live", live.unwrap_or_else(|| file.content.clone()))
+        }
+    };
+
+    let (unified, hunks) = build_diff(&base, &new, &file.path());
+    info!("[diff] <== SUCCESS: {} hunks for file '{}' against the {} content.", hunks.len(), file.name, target);
+    Json(DiffResponse { file_id, base_revision: payload.base_revision, target, unified, hunks }).into_response()
+}
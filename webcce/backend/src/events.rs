@@ -0,0 +1,117 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
+};
+use futures::stream::{self, Stream};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::session::AuthUser;
+use crate::state::{AppState, Project};
+
+// How many events a slow subscriber may fall behind before it is told to
+// reload everything instead.
+const CHANNEL_CAPACITY: usize = 64;
+
+// Something changed in a project. Sent as server-sent events named after the
+// variant, with the fields as JSON data.
+#[derive(Clone, Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProjectEvent {
+    // A file's content changed: saved, autosaved, restored or edited live.
+    Content { file_id: i32, path: String },
+    // Files or folders were added, renamed, moved or deleted.
+    Tree,
+}
+
+impl ProjectEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            ProjectEvent::Content { .. } => "content",
+            ProjectEvent::Tree => "tree",
+        }
+    }
+}
+
+// One broadcast channel per project that someone is listening to. Project ids
+// are unique across rooms, so they are the key.
+#[derive(Clone, Default)]
+pub struct ProjectEvents {
+    channels: Arc<Mutex<HashMap<i32, broadcast::Sender<ProjectEvent>>>>,
+}
+
+impl ProjectEvents {
+    pub fn subscribe(&self, project_id: i32) -> broadcast::Receiver<ProjectEvent> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.entry(project_id).or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0).subscribe()
+    }
+
+    pub fn publish(&self, project_id: i32, event: ProjectEvent) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(sender) = channels.get(&project_id) {
+            // Fails only when nobody is listening any more.
+            if sender.send(event).is_err() {
+                channels.remove(&project_id);
+            }
+        }
+    }
+
+    // Publishes a content change for `file_id`, wherever it is in `projects`.
+    pub fn file_changed(&self, projects: &[Project], file_id: i32) {
+        for project in projects {
+            if let Some(file) = project.files.iter().find(|f| f.id == file_id) {
+                self.publish(project.id, ProjectEvent::Content { file_id, path: file.path() });
+                return;
+            }
+        }
+    }
+}
+
+// Looks up where a live-edited file lives and publishes the change. Takes the
+// file system lock, so it must not be called with the room manager locked.
+pub async fn publish_live_edit(state: &AppState, room_id: &str, file_id: i32) {
+    let file_system = state.file_system.lock().await;
+    if let Some(projects) = file_system.get(room_id) {
+        state.project_events.file_changed(projects, file_id);
+    }
+}
+
+pub async fn project_events(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(project_id): Path<i32>,
+) -> Response {
+    info!("[events] ==> Subscription to project {} by user '{}'", project_id, user.username);
+    let exists = {
+        let file_system = app_state.file_system.lock().await;
+        file_system.get(&user.room_id).is_some_and(|projects| projects.iter().any(|p| p.id == project_id))
+    };
+    if !exists {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    }
+    let receiver = app_state.project_events.subscribe(project_id);
+    Sse::new(event_stream(receiver, project_id)).keep_alive(KeepAlive::default()).into_response()
+}
+
+fn event_stream(receiver: broadcast::Receiver<ProjectEvent>, project_id: i32) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(receiver, move |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            // Some events were dropped; the subscriber can't know what
+            // changed, so have it treat everything as changed.
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("[events] Subscriber to project {} missed {} events.", project_id, skipped);
+                ProjectEvent::Tree
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        let sse = Event::default().event(event.name()).json_data(&event).unwrap_or_default();
+        Some((Ok(sse), receiver))
+    })
+}
@@ -1,18 +1,29 @@
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::Json,
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
-use crate::state::{AppState, Project};
-use tracing::info;
+use std::collections::HashMap;
+use std::time::Instant;
+use crate::events::ProjectEvent;
+use crate::ot::{utf16_len, TextOperation};
+use crate::protocol::ServerMessage;
+use crate::revisions::{self, SaveKind};
+use crate::session::AuthUser;
+use crate::state::{self, AppState, File, Project};
+use crate::ws::{close_deleted_rooms, send_message};
+use tracing::{error, info, warn};
 
-// A struct for the incoming save request
+// A struct for the incoming save request. `expected_version` (or an
+// If-Match header) makes the save fail if someone else saved in between.
 #[derive(Deserialize)]
 pub struct SaveFileRequest {
     id: i32,
     content: String,
+    #[serde(default)]
+    expected_version: Option<u64>,
 }
 
 // A struct for the file content API response
@@ -21,22 +32,155 @@ pub struct FileContentResponse {
     id: i32,
     name: String,
     content: String,
+    version: u64,
+}
+
+#[derive(Serialize)]
+pub struct SaveFileResponse {
+    id: i32,
+    version: u64,
+}
+
+// Sent with 409 Conflict so the client can merge or reload.
+#[derive(Serialize)]
+pub struct SaveConflictResponse {
+    message: &'static str,
+    id: i32,
+    version: u64,
+    content: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateFileRequest {
+    project_id: i32,
+    name: String,
+    // Folder path inside the project; missing folders are created.
+    #[serde(default)]
+    folder: String,
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Deserialize)]
+pub struct RenameFileRequest {
+    id: i32,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct MoveFileRequest {
+    id: i32,
+    project_id: i32,
+    // Target folder; keeps the current folder path when omitted.
+    folder: Option<String>,
+}
+
+// Returned by the create/rename/move endpoints
+#[derive(Serialize)]
+pub struct FileSummary {
+    id: i32,
+    name: String,
+    folder: String,
+    path: String,
+    project_id: i32,
+}
+
+impl FileSummary {
+    pub fn new(file: &File, project_id: i32) -> Self {
+        FileSummary { id: file.id, name: file.name.clone(), folder: file.folder.clone(), path: file.path(), project_id }
+    }
+}
+
+// A project as returned by get_file_tree, with its folders and files nested
+#[derive(Serialize)]
+pub struct ProjectTree {
+    id: i32,
+    name: String,
+    children: Vec<TreeNode>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TreeNode {
+    Folder { name: String, path: String, children: Vec<TreeNode> },
+    File { id: i32, name: String, path: String, binary: bool },
+}
+
+impl From<&Project> for ProjectTree {
+    fn from(project: &Project) -> Self {
+        ProjectTree { id: project.id, name: project.name.clone(), children: tree_children(project, "") }
+    }
+}
+
+// Folders first (sorted by path), then files in creation order.
+fn tree_children(project: &Project, parent: &str) -> Vec<TreeNode> {
+    let folders = project
+        .folders
+        .iter()
+        .filter(|path| !path.is_empty() && state::parent_path(path) == parent)
+        .map(|path| TreeNode::Folder {
+            name: path.rsplit('/').next().unwrap_or(path).to_string(),
+            path: path.clone(),
+            children: tree_children(project, path),
+        });
+    let files = project
+        .files
+        .iter()
+        .filter(|file| file.folder == parent)
+        .map(|file| TreeNode::File { id: file.id, name: file.name.clone(), path: file.path(), binary: file.binary });
+    folders.chain(files).collect()
+}
+
+// Finds which room a file lives in and checks that it is the room the caller
+// logged into. Files in other rooms are reported as forbidden, unknown ids as
+// not found.
+pub fn authorize_file(
+    file_system: &HashMap<String, Vec<Project>>,
+    user: &AuthUser,
+    file_id: i32,
+) -> Result<(), StatusCode> {
+    let room_id = file_system
+        .iter()
+        .find(|(_, projects)| projects.iter().any(|p| p.files.iter().any(|f| f.id == file_id)))
+        .map(|(room_id, _)| room_id);
+
+    match room_id {
+        Some(room_id) if *room_id == user.room_id => Ok(()),
+        Some(room_id) => {
+            info!("[files] FORBIDDEN: User '{}' (room '{}') tried to access file {} in room '{}'.", user.username, user.room_id, file_id, room_id);
+            Err(StatusCode::FORBIDDEN)
+        }
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+pub fn find_file<'a>(
+    file_system: &'a HashMap<String, Vec<Project>>,
+    room_id: &str,
+    file_id: i32,
+) -> Option<&'a File> {
+    file_system.get(room_id)?.iter().flat_map(|p| &p.files).find(|f| f.id == file_id)
 }
 
 // Handler for getting the file tree
 pub async fn get_file_tree(
     State(app_state): State<AppState>,
+    user: AuthUser,
     Path(room_id): Path<String>,
-) -> Result<Json<Vec<Project>>, StatusCode> {
-    info!("[files] ==> API call to get_file_tree for room: '{}'", room_id);
+) -> Result<Json<Vec<ProjectTree>>, StatusCode> {
+    info!("[files] ==> API call to get_file_tree for room: '{}' by user '{}'", room_id, user.username);
+    if room_id != user.room_id {
+        info!("[files] <== FORBIDDEN: User '{}' is logged into room '{}', not '{}'.", user.username, user.room_id, room_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
     let file_system = app_state.file_system.lock().await;
     let keys: Vec<_> = file_system.keys().cloned().collect();
     info!("[files] File system locked. Current rooms are: {:?}", keys);
 
-    match file_system.get(&room_id).cloned() {
+    match file_system.get(&room_id) {
         Some(projects) => {
             info!("[files] <== SUCCESS: Found {} projects for room '{}'.", projects.len(), room_id);
-            Ok(Json(projects))
+            Ok(Json(projects.iter().map(ProjectTree::from).collect()))
         }
         None => {
             info!("[files] <== FAILURE: Room '{}' not found.", room_id);
@@ -48,22 +192,32 @@ pub async fn get_file_tree(
 // Handler for getting a single file's content
 pub async fn get_file_content(
     State(app_state): State<AppState>,
+    user: AuthUser,
     Path(file_id): Path<i32>,
 ) -> Response {
-    info!("[files] ==> API call to get_file_content for file_id: {}", file_id);
+    info!("[files] ==> API call to get_file_content for file_id: {} by user '{}'", file_id, user.username);
     let file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        info!("[files] <== FAILURE: File with id {} not accessible ({}).", file_id, status);
+        return (status, "File not found or not in your room").into_response();
+    }
 
-    for projects in file_system.values() {
+    if let Some(projects) = file_system.get(&user.room_id) {
         for project in projects {
             for file in &project.files {
                 if file.id == file_id {
+                    if file.binary {
+                        info!("[files] <== FAILURE: File {} is binary.", file.id);
+                        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Binary files can only be downloaded").into_response();
+                    }
                     info!("[files] <== SUCCESS: Found file '{}' with id {}.", file.name, file.id);
                     let response = FileContentResponse {
                         id: file.id,
                         name: file.name.clone(),
                         content: file.content.clone(),
+                        version: file.version,
                     };
-                    return (StatusCode::OK, Json(response)).into_response();
+                    return (StatusCode::OK, [(header::ETAG, etag(file.version))], Json(response)).into_response();
                 }
             }
         }
@@ -74,23 +228,482 @@ pub async fn get_file_content(
 
 pub async fn save_file_content(
     State(app_state): State<AppState>,
+    user: AuthUser,
+    headers: HeaderMap,
     Json(payload): Json<SaveFileRequest>,
-) -> StatusCode {
-    info!("[files] ==> API call to save_file_content for file_id: {}", payload.id);
+) -> Response {
+    info!("[files] ==> API call to save_file_content for file_id: {} by user '{}'", payload.id, user.username);
     let mut file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, payload.id) {
+        info!("[files] <== FAILURE: File with id {} not accessible ({}).", payload.id, status);
+        return status.into_response();
+    }
 
-    for projects in file_system.values_mut() {
-        for project in projects {
-            for file in &mut project.files {
-                if file.id == payload.id {
-                    file.content = payload.content;
-                    info!("[files] <== SUCCESS: Saved content for file '{}'.", file.name);
-                    return StatusCode::OK;
+    let current = find_file(&file_system, &user.room_id, payload.id).expect("authorized file exists");
+    if current.binary {
+        info!("[files] <== FAILURE: File {} is binary.", payload.id);
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Binary files cannot be edited as text").into_response();
+    }
+    let if_match = headers.get(header::IF_MATCH).and_then(|value| value.to_str().ok());
+    let stale = payload.expected_version.is_some_and(|expected| expected != current.version)
+        || if_match.is_some_and(|value| !if_match_allows(value, current.version));
+    if stale {
+        info!("[files] <== FAILURE: Save of file {} is based on an old version (now {}).", payload.id, current.version);
+        let conflict = SaveConflictResponse {
+            message: "The file was changed since you loaded it",
+            id: current.id,
+            version: current.version,
+            content: current.content.clone(),
+        };
+        return (StatusCode::CONFLICT, [(header::ETAG, etag(current.version))], Json(conflict)).into_response();
+    }
+    // Edits in an open room that haven't been written back yet would be
+    // overwritten, so the save is refused with the live content instead.
+    let live = {
+        let room_manager = app_state.room_manager.lock().await;
+        room_manager.get(&payload.id).filter(|room| room.is_dirty()).map(|room| room.document.content.clone())
+    };
+    if let Some(content) = live {
+        info!("[files] <== FAILURE: File {} has unsaved edits in its open room.", payload.id);
+        let conflict = SaveConflictResponse {
+            message: "The file has unsaved live edits",
+            id: current.id,
+            version: current.version,
+            content,
+        };
+        return (StatusCode::CONFLICT, [(header::ETAG, etag(current.version))], Json(conflict)).into_response();
+    }
+
+    match store_file_content(&app_state, &mut file_system, &user.room_id, payload.id, payload.content, &user.username, SaveKind::Explicit) {
+        Ok(()) => {
+            // Otherwise the room's next autosave would write its older document back.
+            update_live_document(&app_state, &file_system, &user.room_id, payload.id, &user.username).await;
+            let version = find_file(&file_system, &user.room_id, payload.id).map_or(0, |f| f.version);
+            info!("[files] <== SUCCESS: Saved content for file {} (version {}).", payload.id, version);
+            ([(header::ETAG, etag(version))], Json(SaveFileResponse { id: payload.id, version })).into_response()
+        }
+        Err(status) => {
+            info!("[files] <== FAILURE: Could not save file_id {} ({}).", payload.id, status);
+            status.into_response()
+        }
+    }
+}
+
+pub fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+// True if an If-Match header value ("*" or a list of ETags, weak or strong)
+// matches the file's current version.
+fn if_match_allows(value: &str, version: u64) -> bool {
+    let current = etag(version);
+    value.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == current)
+}
+
+// File names are a single path segment: no separators, no "." or "..", no
+// control characters and at most 255 bytes.
+pub fn validate_file_name(name: &str) -> Result<(), &'static str> {
+    if name.trim().is_empty() {
+        return Err("File name cannot be empty");
+    }
+    if name.len() > 255 {
+        return Err("File name is too long");
+    }
+    if name == "." || name == ".." {
+        return Err("Invalid file name");
+    }
+    if name.chars().any(|c| c == '/' || c == '\\' || c.is_control()) {
+        return Err("File name contains invalid characters");
+    }
+    Ok(())
+}
+
+// Normalizes a folder path like "/css//vendor/" to "css/vendor". Every
+// segment must be a valid file name; "" (the project root) is allowed.
+pub fn normalize_folder_path(path: &str) -> Result<String, &'static str> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in &segments {
+        validate_file_name(segment).map_err(|_| "Invalid folder path")?;
+    }
+    Ok(segments.join("/"))
+}
+
+// Returns (project index, file index) of a file within a room's projects.
+fn locate_file(projects: &[Project], file_id: i32) -> Option<(usize, usize)> {
+    projects.iter().enumerate().find_map(|(p, project)| {
+        project.files.iter().position(|f| f.id == file_id).map(|f| (p, f))
+    })
+}
+
+// Replaces a file's content and records it as a revision by `author`. The
+// content and the manifest are written to storage before touching the
+// in-memory copy so the two never disagree after a failed write.
+pub fn store_file_content(
+    app_state: &AppState,
+    file_system: &mut HashMap<String, Vec<Project>>,
+    room_id: &str,
+    file_id: i32,
+    content: String,
+    author: &str,
+    kind: SaveKind,
+) -> Result<(), StatusCode> {
+    let projects = file_system.get_mut(room_id).ok_or(StatusCode::NOT_FOUND)?;
+    let (p, f) = locate_file(projects, file_id).ok_or(StatusCode::NOT_FOUND)?;
+    let file = &projects[p].files[f];
+    if file.binary {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    if file.content == content {
+        return Ok(());
+    }
+    let updated = File { content, version: file.version + 1, ..file.clone() };
+    if let Err(e) = app_state.storage.save_file(room_id, &updated) {
+        error!("[files] Could not persist file '{}': {}", file.name, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    // The version lives in the manifest.
+    update_projects(app_state, room_id, projects, |projects| projects[p].files[f] = updated.clone())?;
+    revisions::record_revision(app_state, room_id, file_id, author, &updated.content, kind);
+    app_state.project_events.file_changed(projects, file_id);
+    Ok(())
+}
+
+pub fn persist_manifest(app_state: &AppState, room_id: &str, projects: &[Project]) -> Result<(), StatusCode> {
+    app_state.storage.save_manifest(room_id, projects).map_err(|e| {
+        error!("[files] Could not persist room '{}': {}", room_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// After a save from outside the room (a REST save or a restore), brings the
+// open room of `file_id`, if any, in line with the file: the new content is
+// applied as one edit by `username` so everyone in the room sees it, and the
+// room counts as saved at the file's new version.
+pub async fn update_live_document(
+    app_state: &AppState,
+    file_system: &HashMap<String, Vec<Project>>,
+    room_id: &str,
+    file_id: i32,
+    username: &str,
+) {
+    let Some(file) = find_file(file_system, room_id, file_id) else { return };
+    // Lock order: the caller holds the file system before the room manager.
+    let mut room_manager = app_state.room_manager.lock().await;
+    let Some(room) = room_manager.get_mut(&file_id) else { return };
+    if room.document.content != file.content {
+        let mut operation = TextOperation::new();
+        operation.delete(utf16_len(&room.document.content)).insert(&file.content);
+        let base = room.document.revision();
+        match room.apply_edit(base, operation, username, Instant::now()) {
+            Ok(applied) => {
+                let revision = room.document.revision();
+                for connection in room.connections.values() {
+                    send_message(&connection.sender, &ServerMessage::Edit { revision, operation: &applied, username });
                 }
             }
+            Err(e) => {
+                warn!("[files] Could not apply the save to the live document of file {}: {}", file_id, e);
+                return;
+            }
+        }
+    }
+    let revision = room.document.revision();
+    room.mark_saved(revision);
+    room.file_version = file.version;
+}
+
+// Changes a room's projects the way store_file_content changes a file:
+// `change` edits a copy, which is persisted before it replaces the in-memory
+// projects, so a failed write leaves memory matching the disk.
+pub fn update_projects<T>(
+    app_state: &AppState,
+    room_id: &str,
+    projects: &mut Vec<Project>,
+    change: impl FnOnce(&mut Vec<Project>) -> T,
+) -> Result<T, StatusCode> {
+    let mut updated = projects.clone();
+    let result = change(&mut updated);
+    persist_manifest(app_state, room_id, &updated)?;
+    *projects = updated;
+    Ok(result)
+}
+
+pub async fn create_file(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<CreateFileRequest>,
+) -> Response {
+    info!("[files] ==> API call to create_file '{}' in project {} by user '{}'", payload.name, payload.project_id, user.username);
+    if let Err(message) = validate_file_name(&payload.name) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let folder = match normalize_folder_path(&payload.folder) {
+        Ok(folder) => folder,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Room not found").into_response();
+    };
+    let Some(p) = projects.iter().position(|p| p.id == payload.project_id) else {
+        info!("[files] <== FAILURE: Project {} not found in room '{}'.", payload.project_id, user.room_id);
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let project = &projects[p];
+    if project.file_at(&folder, &payload.name).is_some() || project.has_folder(&state::join_path(&folder, &payload.name)) {
+        info!("[files] <== FAILURE: '{}' already exists in project {}.", payload.name, project.id);
+        return (StatusCode::CONFLICT, "A file with that name already exists").into_response();
+    }
+    if project.folder_blocked(&folder) {
+        info!("[files] <== FAILURE: A file is in the way of folder '{}' in project {}.", folder, project.id);
+        return (StatusCode::CONFLICT, "A file already exists where the folder would be").into_response();
+    }
+
+    let file = File { id: state::allocate_file_id(), name: payload.name, folder, content: payload.content, version: 1, binary: false };
+    if let Err(e) = app_state.storage.save_file(&user.room_id, &file) {
+        error!("[files] <== FAILURE: Could not persist new file '{}': {}", file.name, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Could not save changes").into_response();
+    }
+    revisions::record_revision(&app_state, &user.room_id, file.id, &user.username, &file.content, SaveKind::Explicit);
+    let summary = FileSummary::new(&file, payload.project_id);
+    let added = update_projects(&app_state, &user.room_id, projects, |projects| {
+        projects[p].ensure_folder(&file.folder);
+        projects[p].files.push(file);
+    });
+    if let Err(status) = added {
+        return (status, "Could not save changes").into_response();
+    }
+    app_state.project_events.publish(payload.project_id, ProjectEvent::Tree);
+    info!("[files] <== SUCCESS: Created file '{}' with id {}.", summary.name, summary.id);
+    (StatusCode::CREATED, Json(summary)).into_response()
+}
+
+pub async fn rename_file(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<RenameFileRequest>,
+) -> Response {
+    info!("[files] ==> API call to rename_file {} to '{}' by user '{}'", payload.id, payload.name, user.username);
+    if let Err(message) = validate_file_name(&payload.name) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let mut file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, payload.id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let projects = file_system.get_mut(&user.room_id).expect("authorized room exists");
+    let (p, f) = locate_file(projects, payload.id).expect("authorized file exists");
+
+    let project = &projects[p];
+    let folder = &project.files[f].folder;
+    let taken = project.file_at(folder, &payload.name).is_some_and(|other| other.id != payload.id)
+        || project.has_folder(&state::join_path(folder, &payload.name));
+    if taken {
+        return (StatusCode::CONFLICT, "A file with that name already exists").into_response();
+    }
+    let renamed = update_projects(&app_state, &user.room_id, projects, |projects| {
+        projects[p].files[f].name = payload.name;
+        FileSummary::new(&projects[p].files[f], projects[p].id)
+    });
+    let summary = match renamed {
+        Ok(summary) => summary,
+        Err(status) => return (status, "Could not save changes").into_response(),
+    };
+    app_state.project_events.publish(summary.project_id, ProjectEvent::Tree);
+    info!("[files] <== SUCCESS: Renamed file {} to '{}'.", summary.id, summary.name);
+    Json(summary).into_response()
+}
+
+pub async fn move_file(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<MoveFileRequest>,
+) -> Response {
+    info!("[files] ==> API call to move_file {} to project {} by user '{}'", payload.id, payload.project_id, user.username);
+    let mut file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, payload.id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let projects = file_system.get_mut(&user.room_id).expect("authorized room exists");
+    let (p, f) = locate_file(projects, payload.id).expect("authorized file exists");
+    let Some(target) = projects.iter().position(|project| project.id == payload.project_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let folder = match payload.folder.as_deref().map(normalize_folder_path) {
+        None => projects[p].files[f].folder.clone(),
+        Some(Ok(folder)) => folder,
+        Some(Err(message)) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    if target != p || folder != projects[p].files[f].folder {
+        let name = &projects[p].files[f].name;
+        if projects[target].file_at(&folder, name).is_some() || projects[target].has_folder(&state::join_path(&folder, name)) {
+            return (StatusCode::CONFLICT, "A file with that name already exists in the target folder").into_response();
+        }
+        if projects[target].folder_blocked(&folder) {
+            return (StatusCode::CONFLICT, "A file already exists where the folder would be").into_response();
+        }
+        let moved = update_projects(&app_state, &user.room_id, projects, |projects| {
+            let mut file = projects[p].files.remove(f);
+            file.folder = folder;
+            projects[target].ensure_folder(&file.folder);
+            projects[target].files.push(file);
+        });
+        if let Err(status) = moved {
+            return (status, "Could not save changes").into_response();
+        }
+        app_state.project_events.publish(projects[p].id, ProjectEvent::Tree);
+        if target != p {
+            app_state.project_events.publish(projects[target].id, ProjectEvent::Tree);
         }
     }
 
-    info!("[files] <== FAILURE: Could not find file_id {} to save.", payload.id);
-    StatusCode::NOT_FOUND
+    let file = projects[target].files.iter().find(|file| file.id == payload.id).expect("moved file exists");
+    info!("[files] <== SUCCESS: File {} is now at '{}' in project {}.", file.id, file.path(), payload.project_id);
+    Json(FileSummary::new(file, payload.project_id)).into_response()
+}
+
+pub async fn delete_file(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(file_id): Path<i32>,
+) -> Response {
+    info!("[files] ==> API call to delete_file {} by user '{}'", file_id, user.username);
+    let mut file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let projects = file_system.get_mut(&user.room_id).expect("authorized room exists");
+    let (p, f) = locate_file(projects, file_id).expect("authorized file exists");
+    let file = match update_projects(&app_state, &user.room_id, projects, |projects| projects[p].files.remove(f)) {
+        Ok(file) => file,
+        Err(status) => return (status, "Could not save changes").into_response(),
+    };
+    app_state.project_events.publish(projects[p].id, ProjectEvent::Tree);
+    close_deleted_rooms(&app_state, [file_id]).await;
+    if let Err(e) = app_state.storage.delete_file(&user.room_id, file_id) {
+        error!("[files] Could not remove content of deleted file {}: {}", file_id, e);
+    }
+    info!("[files] <== SUCCESS: Deleted file '{}' ({}).", file.name, file_id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ProjectEvents;
+    use crate::llm::MockProvider;
+    use crate::session::SessionKeys;
+    use crate::storage::Storage;
+    use crate::users::FileUserStore;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn user(room_id: &str) -> AuthUser {
+        AuthUser { username: "alice".to_string(), room_id: room_id.to_string() }
+    }
+
+    fn file(id: i32) -> File {
+        File { id, name: format!("{}.txt", id), folder: String::new(), content: String::new(), version: 1, binary: false }
+    }
+
+    fn rooms() -> HashMap<String, Vec<Project>> {
+        let project = |id, files| Project { id, name: "p".to_string(), folders: Vec::new(), files };
+        HashMap::from([
+            ("room1".to_string(), vec![project(1, vec![file(10)])]),
+            ("room2".to_string(), vec![project(2, vec![file(20)])]),
+        ])
+    }
+
+    fn test_state(name: &str) -> AppState {
+        let dir = std::env::temp_dir().join(format!("webcce-files-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        AppState {
+            file_system: Arc::new(Mutex::new(rooms())),
+            room_manager: Arc::new(Mutex::new(HashMap::new())),
+            chat_provider: Arc::new(MockProvider),
+            conversations: Arc::new(Mutex::new(HashMap::new())),
+            conversation_turns: Arc::new(Mutex::new(HashMap::new())),
+            sessions: SessionKeys::from_env(),
+            user_store: Arc::new(FileUserStore::new(dir.join("users.txt"))),
+            storage: Arc::new(Storage::new(dir)),
+            project_events: ProjectEvents::default(),
+        }
+    }
+
+    // Saves "new" into file 10 and returns the status and the content afterwards.
+    async fn save(state: &AppState, expected_version: Option<u64>, if_match: Option<&str>) -> (StatusCode, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = if_match {
+            headers.insert(header::IF_MATCH, value.parse().unwrap());
+        }
+        let request = SaveFileRequest { id: 10, content: "new".to_string(), expected_version };
+        let response = save_file_content(State(state.clone()), user("room1"), headers, Json(request)).await;
+        let file_system = state.file_system.lock().await;
+        (response.status(), find_file(&file_system, "room1", 10).unwrap().content.clone())
+    }
+
+    #[test]
+    fn files_in_the_callers_room_are_allowed() {
+        assert_eq!(authorize_file(&rooms(), &user("room1"), 10), Ok(()));
+    }
+
+    #[test]
+    fn files_in_other_rooms_are_forbidden() {
+        assert_eq!(authorize_file(&rooms(), &user("room1"), 20), Err(StatusCode::FORBIDDEN));
+        assert_eq!(authorize_file(&rooms(), &user("room3"), 10), Err(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn unknown_files_are_not_found() {
+        assert_eq!(authorize_file(&rooms(), &user("room1"), 99), Err(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn if_match_accepts_the_current_version_only() {
+        assert!(if_match_allows("\"3\"", 3));
+        assert!(if_match_allows("W/\"3\"", 3));
+        assert!(if_match_allows("\"1\", \"3\"", 3));
+        assert!(if_match_allows("*", 3));
+        assert!(!if_match_allows("\"2\"", 3));
+        assert!(!if_match_allows("3", 3));
+    }
+
+    #[tokio::test]
+    async fn saves_based_on_the_current_version_succeed() {
+        let state = test_state("current");
+        assert_eq!(save(&state, Some(1), None).await, (StatusCode::OK, "new".to_string()));
+        assert_eq!(save(&state, None, Some("\"2\"")).await, (StatusCode::OK, "new".to_string()));
+        assert_eq!(save(&state, None, None).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn stale_expected_versions_conflict() {
+        let state = test_state("expected-version");
+        assert_eq!(save(&state, Some(0), None).await, (StatusCode::CONFLICT, String::new()));
+        assert_eq!(save(&state, Some(2), None).await, (StatusCode::CONFLICT, String::new()));
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_headers_conflict() {
+        let state = test_state("if-match");
+        assert_eq!(save(&state, None, Some("\"0\"")).await, (StatusCode::CONFLICT, String::new()));
+        assert_eq!(save(&state, Some(1), Some("\"0\"")).await, (StatusCode::CONFLICT, String::new()));
+    }
+
+    #[tokio::test]
+    async fn saves_over_unsaved_live_edits_conflict() {
+        let state = test_state("live-edits");
+        let mut room = state::Room::new("room1", String::new(), 1);
+        let mut operation = TextOperation::new();
+        operation.insert("live");
+        room.apply_edit(0, operation, "bob", Instant::now()).unwrap();
+        state.room_manager.lock().await.insert(10, room);
+        assert_eq!(save(&state, Some(1), None).await, (StatusCode::CONFLICT, String::new()));
+
+        state.room_manager.lock().await.get_mut(&10).unwrap().mark_saved(1);
+        assert_eq!(save(&state, Some(1), None).await, (StatusCode::OK, "new".to_string()));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,169 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use crate::files::{normalize_folder_path, update_projects};
+use crate::events::ProjectEvent;
+use crate::session::AuthUser;
+use crate::state::{self, AppState, Project};
+use crate::ws::close_deleted_rooms;
+use tracing::{error, info};
+
+#[derive(Deserialize)]
+pub struct FolderRequest {
+    project_id: i32,
+    path: String,
+}
+
+#[derive(Deserialize)]
+pub struct MoveFolderRequest {
+    project_id: i32,
+    path: String,
+    new_path: String,
+}
+
+#[derive(Serialize)]
+pub struct FolderSummary {
+    project_id: i32,
+    path: String,
+}
+
+// Normalizes the path and rejects the project root, which can't be created,
+// moved or deleted.
+fn folder_path(path: &str) -> Result<String, &'static str> {
+    match normalize_folder_path(path)? {
+        path if path.is_empty() => Err("Folder path cannot be empty"),
+        path => Ok(path),
+    }
+}
+
+// True if a folder or a file already occupies `path`, or a file is in the
+// way of one of its ancestors.
+fn path_taken(project: &Project, path: &str) -> bool {
+    project.has_folder(path) || project.folder_blocked(path)
+}
+
+pub async fn create_folder(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<FolderRequest>,
+) -> Response {
+    info!("[folders] ==> API call to create_folder '{}' in project {} by user '{}'", payload.path, payload.project_id, user.username);
+    let path = match folder_path(&payload.path) {
+        Ok(path) => path,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let Some(index) = projects.iter().position(|p| p.id == payload.project_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    if path_taken(&projects[index], &path) {
+        return (StatusCode::CONFLICT, "A file or folder already exists at that path or above it").into_response();
+    }
+    if let Err(status) = update_projects(&app_state, &user.room_id, projects, |projects| projects[index].ensure_folder(&path)) {
+        return (status, "Could not save changes").into_response();
+    }
+    app_state.project_events.publish(payload.project_id, ProjectEvent::Tree);
+    info!("[folders] <== SUCCESS: Created folder '{}' in project {}.", path, payload.project_id);
+    (StatusCode::CREATED, Json(FolderSummary { project_id: payload.project_id, path })).into_response()
+}
+
+// Moves (or renames) a folder within its project, along with everything in it.
+pub async fn move_folder(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<MoveFolderRequest>,
+) -> Response {
+    info!("[folders] ==> API call to move_folder '{}' to '{}' in project {} by user '{}'", payload.path, payload.new_path, payload.project_id, user.username);
+    let (path, new_path) = match (folder_path(&payload.path), folder_path(&payload.new_path)) {
+        (Ok(path), Ok(new_path)) => (path, new_path),
+        (Err(message), _) | (_, Err(message)) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    if state::is_within(&new_path, &path) {
+        return (StatusCode::BAD_REQUEST, "A folder cannot be moved into itself").into_response();
+    }
+
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let Some(index) = projects.iter().position(|p| p.id == payload.project_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    if !projects[index].has_folder(&path) {
+        return (StatusCode::NOT_FOUND, "Folder not found").into_response();
+    }
+    if path_taken(&projects[index], &new_path) {
+        return (StatusCode::CONFLICT, "A file or folder already exists at that path or above it").into_response();
+    }
+
+    let rebase = |p: &str| format!("{}{}", new_path, &p[path.len()..]);
+    let moved = update_projects(&app_state, &user.room_id, projects, |projects| {
+        let project = &mut projects[index];
+        for folder in project.folders.iter_mut().filter(|f| state::is_within(f, &path)) {
+            *folder = rebase(folder);
+        }
+        for file in project.files.iter_mut().filter(|f| state::is_within(&f.folder, &path)) {
+            file.folder = rebase(&file.folder);
+        }
+        project.ensure_folder(&new_path);
+    });
+    if let Err(status) = moved {
+        return (status, "Could not save changes").into_response();
+    }
+    app_state.project_events.publish(payload.project_id, ProjectEvent::Tree);
+    info!("[folders] <== SUCCESS: Moved folder '{}' to '{}'.", path, new_path);
+    Json(FolderSummary { project_id: payload.project_id, path: new_path }).into_response()
+}
+
+// Deletes a folder with all of its subfolders and files.
+pub async fn delete_folder(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<FolderRequest>,
+) -> Response {
+    info!("[folders] ==> API call to delete_folder '{}' in project {} by user '{}'", payload.path, payload.project_id, user.username);
+    let path = match folder_path(&payload.path) {
+        Ok(path) => path,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let Some(index) = projects.iter().position(|p| p.id == payload.project_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    if !projects[index].has_folder(&path) {
+        return (StatusCode::NOT_FOUND, "Folder not found").into_response();
+    }
+
+    let deleted = update_projects(&app_state, &user.room_id, projects, |projects| {
+        let project = &mut projects[index];
+        project.folders.retain(|f| !state::is_within(f, &path));
+        let (removed, kept): (Vec<_>, Vec<_>) = project.files.drain(..).partition(|f| state::is_within(&f.folder, &path));
+        project.files = kept;
+        removed
+    });
+    let removed = match deleted {
+        Ok(removed) => removed,
+        Err(status) => return (status, "Could not save changes").into_response(),
+    };
+    close_deleted_rooms(&app_state, removed.iter().map(|f| f.id)).await;
+    for file in &removed {
+        if let Err(e) = app_state.storage.delete_file(&user.room_id, file.id) {
+            error!("[folders] Could not remove content of file {}: {}", file.id, e);
+        }
+    }
+    app_state.project_events.publish(payload.project_id, ProjectEvent::Tree);
+    info!("[folders] <== SUCCESS: Deleted folder '{}' and {} files.", path, removed.len());
+    StatusCode::NO_CONTENT.into_response()
+}
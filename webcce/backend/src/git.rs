@@ -0,0 +1,624 @@
+use axum::{
+    extract::{Multipart, Path, State},
+    http::StatusCode,
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use gix::bstr::ByteSlice;
+use gix::features::zlib::{Decompress, FlushDecompress, Status};
+use gix::objs::tree::{Entry as TreeEntry, EntryKind};
+use gix::objs::{Commit, Tree};
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit};
+use gix::refs::Target;
+use gix::ObjectId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tracing::{error, info};
+
+use crate::archive::{self, entry_path, Entry};
+use crate::assets::form_error;
+use crate::files::validate_file_name;
+use crate::revisions::Revision;
+use crate::session::{now_secs, AuthUser};
+use crate::state::{self, AppState, Project};
+
+// Exported repositories are bare, with the project on this branch.
+const BRANCH: &str = "main";
+
+// Limits on the pack inside a bundle, checked before it is indexed. The
+// pack holds the whole history, so it may inflate to more than the files
+// being imported.
+const MAX_PACK_OBJECTS: u32 = 100_000;
+const MAX_PACK_INFLATED: u64 = 4 * archive::MAX_UNPACKED_SIZE;
+// Enough of a delta's start to hold its two sizes.
+const DELTA_START: usize = 20;
+
+// Names bundle scratch directories apart.
+static NEXT_SCRATCH_ID: AtomicU64 = AtomicU64::new(1);
+
+type GitError = (StatusCode, &'static str);
+
+// Logs why a git operation failed and answers with `message`. The details
+// name paths on the server, so they stay in the log.
+fn rejected<E: Display>(message: &'static str) -> impl FnOnce(E) -> GitError {
+    move |e| {
+        info!("[git] {}: {}", message, e);
+        (StatusCode::BAD_REQUEST, message)
+    }
+}
+
+fn failed<E: Display>(message: &'static str) -> impl FnOnce(E) -> GitError {
+    move |e| {
+        error!("[git] {}: {}", message, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GitExportResponse {
+    path: String,
+    branch: &'static str,
+    head: String,
+    commits: usize,
+}
+
+// What an export needs, gathered under the file system lock so the
+// repository can be written without holding it.
+struct ProjectHistory {
+    name: String,
+    // Every saved revision of the project's text files with the file's
+    // current path, oldest first.
+    revisions: Vec<(String, Revision)>,
+    // The project as it is now, binary files included.
+    snapshot: Vec<(String, Vec<u8>)>,
+}
+
+fn gather_history(app_state: &AppState, room_id: &str, project: &Project) -> Result<ProjectHistory, GitError> {
+    let mut revisions = Vec::new();
+    let mut snapshot = Vec::new();
+    for file in &project.files {
+        if file.binary {
+            let bytes = app_state.storage.read_file(room_id, file.id).map_err(failed("Could not read project files"))?;
+            snapshot.push((file.path(), bytes));
+            continue;
+        }
+        let history = app_state.storage.load_revisions(room_id, file.id).map_err(failed("Could not read revisions"))?;
+        revisions.extend(history.into_iter().map(|revision| (file.path(), revision)));
+        snapshot.push((file.path(), file.content.clone().into_bytes()));
+    }
+    // Stable, so revisions saved in the same second keep their order.
+    revisions.sort_by_key(|(_, revision)| revision.timestamp);
+    Ok(ProjectHistory { name: project.name.clone(), revisions, snapshot })
+}
+
+// No real addresses are known, so authors get one under a reserved domain.
+// Autosaves list several editors; the first one names the address.
+fn signature(author: &str, seconds: u64) -> gix::actor::Signature {
+    let login = author.split(", ").next().unwrap_or(author);
+    gix::actor::Signature {
+        name: author.into(),
+        email: format!("{}@webcce.invalid", login.replace(char::is_whitespace, "_")).into(),
+        time: gix::date::Time { seconds: seconds as i64, offset: 0 },
+    }
+}
+
+fn write_commit(repo: &gix::Repository, tree: ObjectId, parent: Option<ObjectId>, author: &str, seconds: u64, message: &str) -> Result<ObjectId, GitError> {
+    let signature = signature(author, seconds);
+    let commit = Commit {
+        tree,
+        parents: parent.into_iter().collect(),
+        author: signature.clone(),
+        committer: signature,
+        encoding: None,
+        message: format!("{}\n", message).into(),
+        extra_headers: Vec::new(),
+    };
+    Ok(repo.write_object(&commit).map_err(failed("Could not write commit"))?.detach())
+}
+
+// Writes the nested trees for a flat map of paths to blobs.
+fn write_tree(repo: &gix::Repository, files: &BTreeMap<String, ObjectId>) -> Result<ObjectId, GitError> {
+    write_subtree(repo, files.iter().map(|(path, id)| (path.as_str(), *id)).collect())
+}
+
+fn write_subtree(repo: &gix::Repository, files: Vec<(&str, ObjectId)>) -> Result<ObjectId, GitError> {
+    let mut entries = Vec::new();
+    let mut folders: BTreeMap<&str, Vec<(&str, ObjectId)>> = BTreeMap::new();
+    for (path, oid) in files {
+        match path.split_once('/') {
+            Some((folder, rest)) => folders.entry(folder).or_default().push((rest, oid)),
+            None => entries.push(TreeEntry { mode: EntryKind::Blob.into(), filename: path.into(), oid }),
+        }
+    }
+    for (folder, children) in folders {
+        let oid = write_subtree(repo, children)?;
+        entries.push(TreeEntry { mode: EntryKind::Tree.into(), filename: folder.into(), oid });
+    }
+    // Git's order, where folders sort as if their name ended in '/'.
+    entries.sort();
+    Ok(repo.write_object(&Tree { entries }).map_err(failed("Could not write tree"))?.detach())
+}
+
+// Writes one commit per saved revision, then one by `exporter` for whatever
+// the revisions don't cover (binary files, content saved before revisions
+// were kept). Revision commits only depend on the history, so exporting
+// again reproduces them with the same ids; only the snapshot on top can
+// differ from the last export.
+fn export_repository(dir: &std::path::Path, history: ProjectHistory, exporter: &str) -> Result<(ObjectId, usize), GitError> {
+    let repo = if dir.exists() {
+        gix::open(dir).map_err(failed("Could not open repository"))?
+    } else {
+        std::fs::create_dir_all(dir).map_err(failed("Could not create repository"))?;
+        gix::init_bare(dir).map_err(failed("Could not create repository"))?
+    };
+
+    let mut files = BTreeMap::new();
+    let mut tree = None;
+    let mut head = None;
+    let mut commits = 0;
+    for (path, revision) in history.revisions {
+        let blob = repo.write_blob(revision.content.as_bytes()).map_err(failed("Could not write file"))?.detach();
+        let message = match files.insert(path.clone(), blob) {
+            None => format!("Add {}", path),
+            Some(previous) if previous == blob => continue,
+            Some(_) => format!("Update {}", path),
+        };
+        let id = write_tree(&repo, &files)?;
+        head = Some(write_commit(&repo, id, head, &revision.author, revision.timestamp, &message)?);
+        tree = Some(id);
+        commits += 1;
+    }
+
+    let mut current = BTreeMap::new();
+    for (path, bytes) in history.snapshot {
+        current.insert(path, repo.write_blob(&bytes).map_err(failed("Could not write file"))?.detach());
+    }
+    let id = write_tree(&repo, &current)?;
+    if tree != Some(id) {
+        let message = format!("Snapshot of {}", history.name);
+        head = Some(write_commit(&repo, id, head, exporter, now_secs(), &message)?);
+        commits += 1;
+    }
+    let head = head.expect("the snapshot commit is written when there is no other");
+
+    let branch = format!("refs/heads/{}", BRANCH);
+    repo.reference(branch.as_str(), head, PreviousValue::Any, "webcce export").map_err(failed("Could not update branch"))?;
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange::default(),
+            expected: PreviousValue::Any,
+            new: Target::Symbolic(branch.as_str().try_into().expect("valid branch name")),
+        },
+        name: "HEAD".try_into().expect("valid reference name"),
+        deref: false,
+    })
+    .map_err(failed("Could not update HEAD"))?;
+    Ok((head, commits))
+}
+
+// Handler for writing a project's history to a bare repository in the
+// room's git directory, where it can be cloned or pushed from.
+pub async fn export_project(State(app_state): State<AppState>, user: AuthUser, Path(project_id): Path<i32>) -> Response {
+    info!("[git] ==> API call to export_project {} by user '{}'", project_id, user.username);
+    let history = {
+        let file_system = app_state.file_system.lock().await;
+        let Some(project) = file_system.get(&user.room_id).and_then(|projects| projects.iter().find(|p| p.id == project_id)) else {
+            return (StatusCode::NOT_FOUND, "Project not found").into_response();
+        };
+        match gather_history(&app_state, &user.room_id, project) {
+            Ok(history) => history,
+            Err(failure) => return failure.into_response(),
+        }
+    };
+
+    // The path is reported relative to the room's git directory, which is
+    // also how imports name a repository.
+    let name = format!("{}.git", project_id);
+    let target = app_state.storage.git_dir(&user.room_id).join(&name);
+    let exporter = user.username.clone();
+    let result = tokio::task::spawn_blocking(move || export_repository(&target, history, &exporter))
+        .await
+        .unwrap_or_else(|e| Err(failed("Export was interrupted")(e)));
+    match result {
+        Ok((head, commits)) => {
+            info!("[git] <== SUCCESS: Exported project {} to '{}' ({} commits, head {}).", project_id, name, commits, head);
+            Json(GitExportResponse { path: name, branch: BRANCH, head: head.to_string(), commits }).into_response()
+        }
+        Err(failure) => failure.into_response(),
+    }
+}
+
+// Reads the tree of `reference` (HEAD by default) as project entries.
+// Symbolic links and submodules have no equivalent in a project and are
+// skipped.
+fn read_repository(repo: &gix::Repository, reference: &str) -> Result<Vec<Entry>, GitError> {
+    let commit = repo
+        .find_reference(reference)
+        .map_err(rejected("Reference not found"))?
+        .peel_to_commit()
+        .map_err(rejected("Reference does not point to a commit"))?;
+    let tree = commit.tree_id().map_err(rejected("Could not read the repository"))?.detach();
+    // Walked with a stack rather than recursion, so deeply nested trees
+    // can't exhaust the thread's stack.
+    let mut entries = Vec::new();
+    let mut unpacked = 0;
+    let mut pending = vec![(tree, String::new())];
+    while let Some((tree, prefix)) = pending.pop() {
+        let tree = repo.find_tree(tree).map_err(rejected("Could not read the repository"))?;
+        let decoded = tree.decode().map_err(rejected("Could not read the repository"))?;
+        for entry in &decoded.entries {
+            let name = entry.filename.to_str().map_err(rejected("Repository contains a path that is not UTF-8"))?;
+            validate_file_name(name).map_err(rejected("Repository contains an invalid path"))?;
+            let path = state::join_path(&prefix, name);
+            match entry.mode.kind() {
+                EntryKind::Tree => {
+                    entries.push(Entry::Folder(path.clone()));
+                    pending.push((entry.oid.to_owned(), path));
+                }
+                EntryKind::Blob | EntryKind::BlobExecutable => {
+                    // Sized from its header, so a huge blob is never inflated.
+                    let header = repo.find_header(entry.oid).map_err(rejected("Could not read the repository"))?;
+                    unpacked += header.size();
+                    if unpacked > archive::MAX_UNPACKED_SIZE {
+                        return Err((StatusCode::BAD_REQUEST, "Repository is too large"));
+                    }
+                    let blob = repo.find_blob(entry.oid).map_err(rejected("Could not read the repository"))?;
+                    entries.push(Entry::File(path, blob.data.clone()));
+                }
+                EntryKind::Link | EntryKind::Commit => {}
+            }
+            if entries.len() > archive::MAX_ENTRIES {
+                return Err((StatusCode::BAD_REQUEST, "Repository has too many files"));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// Walks the pack without resolving it, so a small pack that inflates to
+// something huge is turned away before it is indexed. Every entry's size is
+// counted before it is inflated, and a delta also counts the size of the
+// object it rebuilds, which can be far larger than the delta itself.
+fn check_pack(pack: &[u8]) -> Result<(), GitError> {
+    let damaged = (StatusCode::BAD_REQUEST, "The bundle's pack is damaged");
+    let too_large = (StatusCode::BAD_REQUEST, "The bundle is too large");
+    let header = pack.first_chunk::<12>().ok_or(damaged)?;
+    let (_, objects) = gix_pack::data::header::decode(header).map_err(rejected("The bundle's pack is damaged"))?;
+    if objects > MAX_PACK_OBJECTS {
+        return Err((StatusCode::BAD_REQUEST, "The bundle has too many objects"));
+    }
+
+    let mut offset = 12;
+    let mut inflated = 0u64;
+    let mut decompress = Decompress::new();
+    let mut buffer = [0u8; 8192];
+    for _ in 0..objects {
+        let mut rest = pack.get(offset..).ok_or(damaged)?;
+        let entry = gix_pack::data::Entry::from_read(&mut rest, offset as u64, gix::hash::Kind::Sha1.len_in_bytes())
+            .map_err(rejected("The bundle's pack is damaged"))?;
+        offset = pack.len() - rest.len();
+        inflated += entry.decompressed_size;
+        if inflated > MAX_PACK_INFLATED {
+            return Err(too_large);
+        }
+
+        // Inflated only to find where the entry ends, and for a delta to
+        // read the sizes at its start.
+        decompress.reset();
+        let mut start = Vec::new();
+        loop {
+            let (read, written) = (decompress.total_in(), decompress.total_out());
+            let status = decompress
+                .decompress(&pack[offset..], &mut buffer, FlushDecompress::None)
+                .map_err(rejected("The bundle's pack is damaged"))?;
+            let consumed = (decompress.total_in() - read) as usize;
+            let produced = (decompress.total_out() - written) as usize;
+            offset += consumed;
+            if decompress.total_out() > entry.decompressed_size {
+                return Err(damaged);
+            }
+            if start.len() < DELTA_START {
+                start.extend_from_slice(&buffer[..produced.min(DELTA_START - start.len())]);
+            }
+            if status == Status::StreamEnd {
+                break;
+            }
+            if consumed == 0 && produced == 0 {
+                return Err(damaged);
+            }
+        }
+        if decompress.total_out() != entry.decompressed_size {
+            return Err(damaged);
+        }
+        if entry.header.is_delta() {
+            // The base object's size, then the rebuilt object's.
+            let (_, used) = delta_size(&start).ok_or(damaged)?;
+            let (result, _) = delta_size(&start[used..]).ok_or(damaged)?;
+            inflated += result;
+            if inflated > MAX_PACK_INFLATED {
+                return Err(too_large);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads one of the little-endian base-128 sizes a delta starts with.
+// Returns the size and how many bytes it took.
+fn delta_size(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut size = 0u64;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        size |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((size, i + 1));
+        }
+    }
+    None
+}
+
+// A bundle (`git bundle create`) is a text header listing references,
+// followed by a packfile. The pack is indexed into a scratch repository so
+// it can be read like any other. Returns the reference to import by
+// default: HEAD if the bundle has it, otherwise the first one listed.
+fn unpack_bundle(bytes: &[u8], dir: &std::path::Path) -> Result<(gix::Repository, String), GitError> {
+    let split = bytes.windows(2).position(|w| w == b"\n\n").ok_or((StatusCode::BAD_REQUEST, "Not a git bundle"))?;
+    let (header, pack) = (&bytes[..split], &bytes[split + 2..]);
+    let header = std::str::from_utf8(header).map_err(rejected("Not a git bundle"))?;
+    let mut lines = header.lines();
+    if !matches!(lines.next(), Some("# v2 git bundle" | "# v3 git bundle")) {
+        return Err((StatusCode::BAD_REQUEST, "Not a git bundle"));
+    }
+    let mut refs = Vec::new();
+    for line in lines {
+        if let Some(capability) = line.strip_prefix('@') {
+            if capability.starts_with("object-format=") && capability != "object-format=sha1" {
+                return Err((StatusCode::BAD_REQUEST, "Only SHA-1 bundles are supported"));
+            }
+            continue;
+        }
+        if line.starts_with('-') {
+            return Err((StatusCode::BAD_REQUEST, "The bundle depends on commits it doesn't contain; create it with --all"));
+        }
+        let (id, name) = line.split_once(' ').ok_or((StatusCode::BAD_REQUEST, "Not a git bundle"))?;
+        let id = ObjectId::from_hex(id.as_bytes()).map_err(rejected("Not a git bundle"))?;
+        refs.push((id, name.to_string()));
+    }
+    let default = if refs.iter().any(|(_, name)| name == "HEAD") { "HEAD".to_string() } else {
+        refs.first().map(|(_, name)| name.clone()).ok_or((StatusCode::BAD_REQUEST, "The bundle has no references"))?
+    };
+
+    gix::init_bare(dir).map_err(failed("Could not unpack bundle"))?;
+    check_pack(pack)?;
+    let interrupt = AtomicBool::new(false);
+    gix_pack::Bundle::write_to_directory(
+        &mut &pack[..],
+        Some(&dir.join("objects").join("pack")),
+        &mut gix::progress::Discard,
+        &interrupt,
+        None::<gix::objs::find::Never>,
+        Default::default(),
+    )
+    .map_err(rejected("The bundle's pack is damaged"))?;
+    // Opened again so the new pack is picked up.
+    let repo = gix::open(dir).map_err(failed("Could not unpack bundle"))?;
+    for (id, name) in refs {
+        repo.reference(name.as_str(), id, PreviousValue::Any, "webcce bundle").map_err(rejected("The bundle has an invalid reference"))?;
+    }
+    Ok((repo, default))
+}
+
+// Where the project comes from: a repository or bundle in the room's git
+// directory, or an uploaded bundle.
+enum Source {
+    Path(PathBuf),
+    Bundle(Vec<u8>),
+}
+
+fn read_source(source: Source, reference: Option<String>) -> Result<Vec<Entry>, GitError> {
+    let bundle = match source {
+        Source::Path(path) if path.is_dir() => {
+            let repo = gix::open(&path).map_err(rejected("Not a git repository"))?;
+            return read_repository(&repo, reference.as_deref().unwrap_or("HEAD"));
+        }
+        Source::Path(path) => std::fs::read(&path).map_err(rejected("Repository or bundle not found"))?,
+        Source::Bundle(bytes) => bytes,
+    };
+    let scratch = std::env::temp_dir().join(format!("webcce-bundle-{}-{}", std::process::id(), NEXT_SCRATCH_ID.fetch_add(1, Ordering::Relaxed)));
+    let result = unpack_bundle(&bundle, &scratch)
+        .and_then(|(repo, default)| read_repository(&repo, reference.as_deref().unwrap_or(&default)));
+    if let Err(e) = std::fs::remove_dir_all(&scratch) {
+        error!("[git] Could not remove '{}': {}", scratch.display(), e);
+    }
+    result
+}
+
+// Handler for creating a project from the tip of a git repository. The
+// multipart form has:
+//   name   the project name; defaults to the repository or bundle name
+//   path   a bare repository or bundle file, relative to the room's git
+//          directory on the server
+//   file   an uploaded bundle, instead of `path`
+//   ref    the branch, tag or reference to import; HEAD by default
+pub async fn import_project(State(app_state): State<AppState>, user: AuthUser, mut multipart: Multipart) -> Response {
+    info!("[git] ==> API call to import_project in room '{}' by user '{}'", user.room_id, user.username);
+    let (mut name, mut path, mut reference, mut bundle) = (None, None, None, None);
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return form_error(e).into_response(),
+        };
+        let result = match field.name() {
+            Some("name") => field.text().await.map(|text| name = Some(text)),
+            Some("path") => field.text().await.map(|text| path = Some(text)),
+            Some("ref") => field.text().await.map(|text| reference = Some(text).filter(|r| !r.trim().is_empty())),
+            Some("file") => {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+                field.bytes().await.map(|bytes| bundle = Some((file_name, bytes.to_vec())))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return form_error(e).into_response();
+        }
+    }
+
+    let (source, source_name) = match (path, bundle) {
+        (Some(path), None) => {
+            let segments = match entry_path(&path) {
+                Ok(segments) if !segments.is_empty() => segments,
+                _ => return (StatusCode::BAD_REQUEST, "Invalid repository path").into_response(),
+            };
+            let full = segments.iter().fold(app_state.storage.git_dir(&user.room_id), |dir, segment| dir.join(segment));
+            (Source::Path(full), segments.last().unwrap_or(&"").to_string())
+        }
+        (None, Some((file_name, bytes))) => (Source::Bundle(bytes), file_name),
+        _ => return (StatusCode::BAD_REQUEST, "Give either a path or an uploaded bundle").into_response(),
+    };
+    let name = name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| {
+        let base = source_name.rsplit(['/', '\\']).next().unwrap_or_default();
+        base.trim_end_matches(".git").trim_end_matches(".bundle").to_string()
+    });
+    if let Err(message) = crate::projects::validate_project_name(&name) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let entries = match tokio::task::spawn_blocking(move || read_source(source, reference)).await {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(failure)) => {
+            info!("[git] <== FAILURE: Could not import '{}': {}", source_name, failure.1);
+            return failure.into_response();
+        }
+        Err(e) => return failed("Import was interrupted")(e).into_response(),
+    };
+    archive::add_project(&app_state, &user, name, entries, &source_name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix::features::zlib::stream::deflate;
+    use std::io::Write;
+
+    fn export(name: &str, files: Vec<(String, Vec<u8>)>) -> gix::Repository {
+        let dir = std::env::temp_dir().join(format!("webcce-git-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        let history = ProjectHistory { name: name.to_string(), revisions: Vec::new(), snapshot: files };
+        export_repository(&dir, history, "alice").unwrap();
+        gix::open(&dir).unwrap()
+    }
+
+    fn paths(entries: &[Entry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Folder(path) | Entry::File(path, _) => path.as_str(),
+            })
+            .collect()
+    }
+
+    // A pack of (type, declared size, data) entries, each inflating to `data`.
+    // Ref deltas get an all-zero base id.
+    fn pack(entries: &[(u8, u64, &[u8])]) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend((entries.len() as u32).to_be_bytes());
+        for &(kind, size, data) in entries {
+            let mut byte = (kind << 4) | (size & 0x0f) as u8;
+            let mut rest = size >> 4;
+            while rest > 0 {
+                pack.push(byte | 0x80);
+                byte = (rest & 0x7f) as u8;
+                rest >>= 7;
+            }
+            pack.push(byte);
+            if kind == 7 {
+                pack.extend([0; 20]);
+            }
+            let mut zlib = deflate::Write::new(Vec::new());
+            zlib.write_all(data).unwrap();
+            zlib.flush().unwrap();
+            pack.extend(zlib.into_inner());
+        }
+        pack
+    }
+
+    const BLOB: u8 = 3;
+    const REF_DELTA: u8 = 7;
+
+    #[test]
+    fn repositories_are_read_at_a_reference() {
+        let files = vec![("index.html".to_string(), b"hi".to_vec()), ("css/a.css".to_string(), b"b{}".to_vec())];
+        let repo = export("read", files);
+        let entries = read_repository(&repo, "HEAD").unwrap();
+        assert_eq!(paths(&entries), ["css", "index.html", "css/a.css"]);
+        assert_eq!(paths(&read_repository(&repo, "refs/heads/main").unwrap()), paths(&entries));
+        assert_eq!(read_repository(&repo, "refs/heads/missing").err(), Some((StatusCode::BAD_REQUEST, "Reference not found")));
+    }
+
+    #[test]
+    fn repositories_with_too_many_files_are_rejected() {
+        let files = (0..=archive::MAX_ENTRIES).map(|i| (format!("{}.txt", i), b"x".to_vec())).collect();
+        let repo = export("many", files);
+        assert_eq!(read_repository(&repo, "HEAD").err(), Some((StatusCode::BAD_REQUEST, "Repository has too many files")));
+    }
+
+    #[test]
+    fn repositories_too_large_to_unpack_are_rejected() {
+        let repo = export("large", vec![("small.txt".to_string(), b"x".to_vec())]);
+        // A loose blob whose header claims more than the limit. Only the
+        // header is ever read, so the id doesn't have to match the content.
+        let hex = "1".repeat(40);
+        let mut zlib = deflate::Write::new(Vec::new());
+        write!(zlib, "blob {}\0x", archive::MAX_UNPACKED_SIZE + 1).unwrap();
+        zlib.flush().unwrap();
+        let objects = repo.path().join("objects").join(&hex[..2]);
+        std::fs::create_dir_all(&objects).unwrap();
+        std::fs::write(objects.join(&hex[2..]), zlib.into_inner()).unwrap();
+
+        let id = ObjectId::from_hex(hex.as_bytes()).unwrap();
+        let tree = write_tree(&repo, &BTreeMap::from([("large.bin".to_string(), id)])).unwrap();
+        let commit = write_commit(&repo, tree, None, "alice", 0, "Add large.bin").unwrap();
+        repo.reference("refs/heads/large", commit, PreviousValue::Any, "test").unwrap();
+        assert_eq!(read_repository(&repo, "refs/heads/large").err(), Some((StatusCode::BAD_REQUEST, "Repository is too large")));
+    }
+
+    #[test]
+    fn well_formed_packs_pass() {
+        assert_eq!(check_pack(&pack(&[(BLOB, 5, b"hello"), (BLOB, 0, b"")])), Ok(()));
+    }
+
+    #[test]
+    fn damaged_packs_are_rejected() {
+        let damaged = Err((StatusCode::BAD_REQUEST, "The bundle's pack is damaged"));
+        assert_eq!(check_pack(b"PACK"), damaged);
+        assert_eq!(check_pack(b"not a pack at all"), damaged);
+        // More data than the entry declares, or less.
+        assert_eq!(check_pack(&pack(&[(BLOB, 3, b"hello")])), damaged);
+        assert_eq!(check_pack(&pack(&[(BLOB, 9, b"hello")])), damaged);
+        let mut truncated = pack(&[(BLOB, 5, b"hello")]);
+        truncated.truncate(truncated.len() - 4);
+        assert_eq!(check_pack(&truncated), damaged);
+    }
+
+    #[test]
+    fn packs_with_too_many_objects_are_rejected() {
+        let mut header = b"PACK".to_vec();
+        header.extend(2u32.to_be_bytes());
+        header.extend((MAX_PACK_OBJECTS + 1).to_be_bytes());
+        assert_eq!(check_pack(&header), Err((StatusCode::BAD_REQUEST, "The bundle has too many objects")));
+    }
+
+    #[test]
+    fn packs_that_inflate_too_far_are_rejected() {
+        let too_large = Err((StatusCode::BAD_REQUEST, "The bundle is too large"));
+        // Turned away on the declared size, before anything is inflated.
+        assert_eq!(check_pack(&pack(&[(BLOB, MAX_PACK_INFLATED + 1, b"")])), too_large);
+        // A tiny delta claiming to rebuild a huge object.
+        let delta = [0x05, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert_eq!(check_pack(&pack(&[(REF_DELTA, delta.len() as u64, &delta)])), too_large);
+    }
+}
@@ -0,0 +1,447 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::chat::ChatMessage;
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const OPENAI_MODEL: &str = "gpt-5-nano";
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_MODEL: &str = "claude-3-5-haiku-latest";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const MAX_TOKENS: u32 = 800;
+
+#[derive(Debug)]
+pub enum ProviderError {
+    // The provider could not be reached, or the connection failed.
+    Unreachable(String),
+    // The provider answered with an error status.
+    Status { status: u16, body: String },
+    // The reply was not in the format the provider should send.
+    InvalidResponse(String),
+    // A streamed reply ended before the provider said it was complete.
+    CutOff,
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Unreachable(e) => write!(f, "provider unreachable: {}", e),
+            ProviderError::Status { status, body } => write!(f, "provider returned status {}: {}", status, body),
+            ProviderError::InvalidResponse(e) => write!(f, "invalid provider response: {}", e),
+            ProviderError::CutOff => write!(f, "provider stream ended early"),
+        }
+    }
+}
+
+// The pieces of a streamed reply, in order. The channel closes once the reply
+// is complete; an error is always the last item.
+pub type Deltas = mpsc::Receiver<Result<String, ProviderError>>;
+
+// A language model that can answer a conversation, either in one piece or
+// streamed. Streaming stops early when the receiver is dropped.
+pub trait ChatProvider: Send + Sync {
+    fn describe(&self) -> String;
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String, ProviderError>>;
+    fn stream<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<Deltas, ProviderError>>;
+}
+
+// Picks the provider from CHAT_PROVIDER: "openai" (the default, for anything
+// that speaks the OpenAI chat completions API, such as Ollama or llama.cpp),
+// "anthropic" or "mock". CHAT_BASE_URL, CHAT_MODEL and CHAT_API_KEY override
+// the provider's defaults; OPENAI_API_KEY is still read for OpenAI.
+pub fn open_from_env() -> Arc<dyn ChatProvider> {
+    let setting = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let provider: Arc<dyn ChatProvider> = match std::env::var("CHAT_PROVIDER").as_deref() {
+        Ok("mock") => Arc::new(MockProvider),
+        Ok("anthropic") => Arc::new(AnthropicProvider {
+            client: reqwest::Client::new(),
+            base_url: setting("CHAT_BASE_URL").unwrap_or_else(|| ANTHROPIC_BASE_URL.to_string()),
+            model: setting("CHAT_MODEL").unwrap_or_else(|| ANTHROPIC_MODEL.to_string()),
+            api_key: setting("CHAT_API_KEY").or_else(|| setting("ANTHROPIC_API_KEY")).unwrap_or_default(),
+        }),
+        _ => Arc::new(OpenAiProvider {
+            client: reqwest::Client::new(),
+            base_url: setting("CHAT_BASE_URL").unwrap_or_else(|| OPENAI_BASE_URL.to_string()),
+            model: setting("CHAT_MODEL").unwrap_or_else(|| OPENAI_MODEL.to_string()),
+            api_key: setting("CHAT_API_KEY").or_else(|| setting("OPENAI_API_KEY")).unwrap_or_default(),
+        }),
+    };
+    info!("[llm] Using chat provider {}.", provider.describe());
+    provider
+}
+
+fn endpoint(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+// Sends `request` and hands back the response if its status is a success.
+async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, ProviderError> {
+    let resp = request.send().await.map_err(|e| ProviderError::Unreachable(e.to_string()))?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(ProviderError::Status { status: status.as_u16(), body });
+    }
+    Ok(resp)
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(data: &str) -> Result<T, ProviderError> {
+    serde_json::from_str(data).map_err(|e| ProviderError::InvalidResponse(format!("{} in {}", e, data)))
+}
+
+// Reads a server-sent event body one `data:` payload at a time. Event names
+// are ignored: both APIs repeat what they need inside the data.
+struct EventData {
+    resp: reqwest::Response,
+    buffer: Vec<u8>,
+}
+
+impl EventData {
+    fn new(resp: reqwest::Response) -> Self {
+        EventData { resp, buffer: Vec::new() }
+    }
+
+    // The next payload, or None once the body ends.
+    async fn next(&mut self) -> Result<Option<String>, ProviderError> {
+        loop {
+            // Only complete lines are handled; the rest waits for the next chunk.
+            while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some(data) = line.trim_end().strip_prefix("data:") {
+                    return Ok(Some(data.trim_start().to_string()));
+                }
+            }
+            match self.resp.chunk().await {
+                Ok(Some(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(ProviderError::Unreachable(e.to_string())),
+            }
+        }
+    }
+}
+
+// Runs `read` in the background, sending what it produces to the returned
+// receiver. `read` gets a sender for the deltas and reports how it ended.
+fn relay<F, Fut>(read: F) -> Deltas
+where
+    F: FnOnce(mpsc::Sender<Result<String, ProviderError>>) -> Fut,
+    Fut: std::future::Future<Output = Result<(), ProviderError>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(32);
+    let task = read(sender.clone());
+    tokio::spawn(async move {
+        if let Err(e) = task.await {
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+    receiver
+}
+
+// --- OpenAI chat completions ---
+
+pub struct OpenAiProvider {
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    api_key: String,
+}
+
+#[derive(Serialize)]
+struct OaiMsg<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct OaiReq<'a> {
+    model: &'a str,
+    messages: Vec<OaiMsg<'a>>,
+    max_tokens: Option<u32>,
+    // Ask for the reply as server-sent chunks instead of one body.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct OaiChoice {
+    message: Option<OaiMessage>,
+}
+#[derive(Deserialize)]
+struct OaiMessage {
+    content: Option<String>,
+}
+#[derive(Deserialize)]
+struct OaiResp {
+    choices: Vec<OaiChoice>,
+}
+
+// One `data:` line of a streamed completion.
+#[derive(Deserialize)]
+struct OaiChunk {
+    choices: Vec<OaiChunkChoice>,
+}
+#[derive(Deserialize)]
+struct OaiChunkChoice {
+    #[serde(default)]
+    delta: OaiDelta,
+}
+#[derive(Deserialize, Default)]
+struct OaiDelta {
+    content: Option<String>,
+}
+
+impl OpenAiProvider {
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> reqwest::RequestBuilder {
+        let body = OaiReq {
+            model: &self.model,
+            messages: messages.iter().map(|m| OaiMsg { role: &m.role, content: &m.content }).collect(),
+            max_tokens: Some(MAX_TOKENS),
+            stream,
+        };
+        let request = self.client.post(endpoint(&self.base_url, "chat/completions")).json(&body);
+        // Local servers usually run without a key.
+        if self.api_key.is_empty() {
+            request
+        } else {
+            request.bearer_auth(&self.api_key)
+        }
+    }
+}
+
+impl ChatProvider for OpenAiProvider {
+    fn describe(&self) -> String {
+        format!("OpenAI-compatible '{}' at {}", self.model, self.base_url)
+    }
+
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String, ProviderError>> {
+        Box::pin(async move {
+            let resp = send(self.request(messages, false)).await?;
+            let raw = resp.text().await.map_err(|e| ProviderError::Unreachable(e.to_string()))?;
+            let parsed: OaiResp = parse(&raw)?;
+            Ok(parsed.choices.into_iter().next().and_then(|c| c.message.and_then(|m| m.content)).unwrap_or_default())
+        })
+    }
+
+    fn stream<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<Deltas, ProviderError>> {
+        Box::pin(async move {
+            let mut events = EventData::new(send(self.request(messages, true)).await?);
+            Ok(relay(|sender| async move {
+                while let Some(data) = events.next().await? {
+                    if data == "[DONE]" {
+                        return Ok(());
+                    }
+                    let chunk: OaiChunk = parse(&data)?;
+                    for content in chunk.choices.into_iter().filter_map(|c| c.delta.content) {
+                        if !content.is_empty() && sender.send(Ok(content)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                Err(ProviderError::CutOff)
+            }))
+        })
+    }
+}
+
+// --- Anthropic Messages ---
+
+pub struct AnthropicProvider {
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    api_key: String,
+}
+
+#[derive(Serialize)]
+struct AnthropicReq<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    // System prompts are a separate field rather than a message role.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<OaiMsg<'a>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct AnthropicResp {
+    content: Vec<AnthropicBlock>,
+}
+#[derive(Deserialize)]
+struct AnthropicBlock {
+    text: Option<String>,
+}
+
+// The streamed events that matter here; pings, message and block starts and
+// the like are skipped.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicEvent {
+    ContentBlockDelta { delta: AnthropicBlock },
+    MessageStop,
+    Error { error: serde_json::Value },
+    #[serde(other)]
+    Other,
+}
+
+impl AnthropicProvider {
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> reqwest::RequestBuilder {
+        let system: Vec<&str> = messages.iter().filter(|m| m.role == "system").map(|m| m.content.as_str()).collect();
+        let body = AnthropicReq {
+            model: &self.model,
+            max_tokens: MAX_TOKENS,
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            messages: messages
+                .iter()
+                .filter(|m| m.role != "system")
+                .map(|m| OaiMsg { role: &m.role, content: &m.content })
+                .collect(),
+            stream,
+        };
+        self.client
+            .post(endpoint(&self.base_url, "messages"))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body)
+    }
+}
+
+impl ChatProvider for AnthropicProvider {
+    fn describe(&self) -> String {
+        format!("Anthropic '{}' at {}", self.model, self.base_url)
+    }
+
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String, ProviderError>> {
+        Box::pin(async move {
+            let resp = send(self.request(messages, false)).await?;
+            let raw = resp.text().await.map_err(|e| ProviderError::Unreachable(e.to_string()))?;
+            let parsed: AnthropicResp = parse(&raw)?;
+            Ok(parsed.content.into_iter().filter_map(|block| block.text).collect())
+        })
+    }
+
+    fn stream<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<Deltas, ProviderError>> {
+        Box::pin(async move {
+            let mut events = EventData::new(send(self.request(messages, true)).await?);
+            Ok(relay(|sender| async move {
+                while let Some(data) = events.next().await? {
+                    match parse(&data)? {
+                        AnthropicEvent::ContentBlockDelta { delta } => {
+                            let Some(text) = delta.text.filter(|t| !t.is_empty()) else { continue };
+                            if sender.send(Ok(text)).await.is_err() {
+                                return Ok(());
+                            }
+                        }
+                        AnthropicEvent::MessageStop => return Ok(()),
+                        AnthropicEvent::Error { error } => return Err(ProviderError::InvalidResponse(error.to_string())),
+                        AnthropicEvent::Other => {}
+                    }
+                }
+                Err(ProviderError::CutOff)
+            }))
+        })
+    }
+}
+
+// --- Mock ---
+
+// Answers without any network access, always the same way for the same
+// conversation: it echoes the last message, streamed word by word. A last
+// message of "mock:fail" fails the request, and "mock:cutoff" breaks the
+// stream off after the first word.
+pub struct MockProvider;
+
+impl MockProvider {
+    fn reply(messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        let last = messages.last().map(|m| m.content.as_str()).unwrap_or_default();
+        if last == "mock:fail" {
+            return Err(ProviderError::Status { status: 500, body: "mock failure".to_string() });
+        }
+        Ok(format!("You said: {}", last))
+    }
+}
+
+impl ChatProvider for MockProvider {
+    fn describe(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn complete<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String, ProviderError>> {
+        Box::pin(async move { Self::reply(messages) })
+    }
+
+    fn stream<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<Deltas, ProviderError>> {
+        Box::pin(async move {
+            let reply = Self::reply(messages)?;
+            let cut_off = messages.last().is_some_and(|m| m.content == "mock:cutoff");
+            Ok(relay(|sender| async move {
+                for (i, word) in reply.split_inclusive(' ').enumerate() {
+                    if cut_off && i == 1 {
+                        return Err(ProviderError::CutOff);
+                    }
+                    if sender.send(Ok(word.to_string())).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(())
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn said(content: &str) -> Vec<ChatMessage> {
+        vec![ChatMessage { role: "user".to_string(), content: content.to_string(), timestamp: 0 }]
+    }
+
+    async fn collect(mut deltas: Deltas) -> Vec<Result<String, ProviderError>> {
+        let mut items = Vec::new();
+        while let Some(item) = deltas.recv().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn mock_complete_echoes_the_last_message() {
+        let reply = MockProvider.complete(&said("hello world")).await.unwrap();
+        assert_eq!(reply, "You said: hello world");
+    }
+
+    #[tokio::test]
+    async fn mock_complete_fails_on_request() {
+        let result = MockProvider.complete(&said("mock:fail")).await;
+        assert!(matches!(result, Err(ProviderError::Status { status: 500, .. })));
+    }
+
+    #[tokio::test]
+    async fn mock_stream_sends_the_reply_word_by_word() {
+        let deltas = MockProvider.stream(&said("hello world")).await.unwrap();
+        let words: Vec<String> = collect(deltas).await.into_iter().map(Result::unwrap).collect();
+        assert_eq!(words, ["You ", "said: ", "hello ", "world"]);
+    }
+
+    #[tokio::test]
+    async fn mock_stream_fails_before_the_first_delta() {
+        let result = MockProvider.stream(&said("mock:fail")).await;
+        assert!(matches!(result, Err(ProviderError::Status { status: 500, .. })));
+    }
+
+    #[tokio::test]
+    async fn mock_stream_cuts_off_after_the_first_word() {
+        let deltas = MockProvider.stream(&said("mock:cutoff")).await.unwrap();
+        let items = collect(deltas).await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], Ok(word) if word == "You "));
+        assert!(matches!(items[1], Err(ProviderError::CutOff)));
+    }
+}
@@ -1,5 +1,7 @@
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    http::{header, HeaderValue},
+    routing::{delete, get, post},
     Router,
 };
 use std::collections::HashMap;
@@ -8,26 +10,67 @@ use tokio::net::TcpListener;
 use axum::response::IntoResponse;
 use tokio::sync::Mutex;
 use tower_http::{
-    cors::{Any, CorsLayer},
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
     trace::TraceLayer,
 };
 use tracing::Level;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 use std::env;
 
+mod archive;
+mod assets;
 mod auth;
+mod autosave;
 mod state;
 mod files;
+mod folders;
+mod git;
+mod llm;
+mod ot;
+mod preview;
 mod ws;
+mod protocol;
 mod chat;
+mod conversations;
+mod diff;
+mod events;
+mod projects;
+mod revisions;
+mod session;
+mod storage;
+mod users;
 
-use state::{AppState, create_initial_data};
+use session::SessionKeys;
+use state::{AppState, load_file_system};
+use storage::Storage;
+
+const DEFAULT_ALLOWED_ORIGIN: &str = "https://mp2upnhs.my";
 
 async fn root() -> impl IntoResponse {
     println!("Backend is working");
     "Backend is working"                   
 }
 
+// Reads ALLOWED_ORIGINS, a comma-separated list of frontend origins such as
+// "https://mp2upnhs.my,http://localhost:5500". Defaults to the hosted frontend.
+fn allowed_origins() -> Vec<HeaderValue> {
+    let setting = env::var("ALLOWED_ORIGINS").unwrap_or_else(|_| DEFAULT_ALLOWED_ORIGIN.to_string());
+    let origins: Vec<HeaderValue> = setting
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/'))
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| match origin.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!("[main] Ignoring invalid origin '{}' in ALLOWED_ORIGINS.", origin);
+                None
+            }
+        })
+        .collect();
+    tracing::info!("[main] Allowing cross-origin requests from {:?}.", origins);
+    origins
+}
+
 #[tokio::main]
 async fn main() {
     let filter = EnvFilter::builder()
@@ -41,29 +84,71 @@ async fn main() {
 
     tracing::info!("[main] ==> Application starting up...");
 
-    let openai_key = env::var("OPENAI_API_KEY")
-    .expect("OPENAI_API_KEY must be set in environment");
-
+    let storage = Arc::new(Storage::from_env());
     let app_state = AppState {
-        file_system: create_initial_data(),
+        file_system: load_file_system(&storage),
         room_manager: Arc::new(Mutex::new(HashMap::new())),
-        openai_api_key: openai_key,
+        chat_provider: llm::open_from_env(),
+        conversations: Arc::new(Mutex::new(HashMap::new())),
+        conversation_turns: Arc::new(Mutex::new(HashMap::new())),
+        sessions: SessionKeys::from_env(),
+        user_store: users::open_from_env(),
+        storage,
+        project_events: events::ProjectEvents::default(),
     };
+    autosave::spawn(app_state.clone());
 
+    // Credentials (the session cookie) can't be combined with wildcards, so
+    // mirror the methods and headers the browser asks for. Origins are never
+    // mirrored: only the configured frontends may make credentialed calls.
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_origin(AllowOrigin::list(allowed_origins()))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+        // Lets the client name downloaded files.
+        .expose_headers([header::CONTENT_DISPOSITION]);
 
     let app = Router::new()
         .route("/", get(root))
         .route("/signup", post(auth::signup_user))
         .route("/login", post(auth::login_user))
         .route("/api/file-tree/:room_id", get(files::get_file_tree))
-        .route("/api/file/:file_id", get(files::get_file_content))
+        .route("/api/file/:file_id", get(files::get_file_content).delete(files::delete_file))
         .route("/api/file/save", post(files::save_file_content))
-        .route("/ws/:file_id/:username", get(ws::ws_handler))
+        .route("/api/file/create", post(files::create_file))
+        .route("/api/file/rename", post(files::rename_file))
+        .route("/api/file/move", post(files::move_file))
+        .route("/api/file/upload", post(assets::upload_files).layer(DefaultBodyLimit::max(assets::MAX_UPLOAD_SIZE)))
+        .route("/api/file/:file_id/raw", get(assets::download_file))
+        .route("/api/file/:file_id/diff", post(diff::diff_file))
+        .route("/api/file/:file_id/revisions", get(revisions::list_revisions))
+        .route("/api/file/:file_id/revisions/:revision_id", get(revisions::get_revision))
+        .route("/api/file/:file_id/revisions/:revision_id/restore", post(revisions::restore_revision))
+        .route("/api/folder/create", post(folders::create_folder))
+        .route("/api/folder/move", post(folders::move_folder))
+        .route("/api/folder/delete", post(folders::delete_folder))
+        .route("/api/projects", get(projects::list_projects))
+        .route("/api/project/create", post(projects::create_project))
+        .route("/api/project/rename", post(projects::rename_project))
+        .route("/api/project/:project_id", delete(projects::delete_project))
+        .route("/api/project/:project_id/events", get(events::project_events))
+        .route("/api/project/:project_id/preview-token", post(preview::issue_preview_token))
+        .route("/api/project/:project_id/export", get(archive::export_project))
+        .route("/api/project/import", post(archive::import_project).layer(DefaultBodyLimit::max(archive::MAX_IMPORT_SIZE)))
+        .route("/api/project/:project_id/git/export", post(git::export_project))
+        .route("/api/project/import/git", post(git::import_project).layer(DefaultBodyLimit::max(archive::MAX_IMPORT_SIZE)))
+        .route("/api/room/export", get(archive::export_room))
+        .route("/api/room/create", post(projects::create_room))
+        .route("/ws/:file_id", get(ws::ws_handler))
+        .route("/preview/:token", get(preview::preview_root))
+        .route("/preview/:token/", get(preview::preview_root))
+        .route("/preview/:token/*path", get(preview::preview_file))
         .route("/chat", post(chat::handle_chat))
+        .route("/chat/stream", post(chat::handle_chat_stream))
+        .route("/api/conversations", get(conversations::list_conversations))
+        .route("/api/conversation/rename", post(conversations::rename_conversation))
+        .route("/api/conversation/:conversation_id", get(conversations::get_conversation).delete(conversations::delete_conversation))
         .with_state(app_state)
         .layer(cors)
         .layer(TraceLayer::new_for_http());
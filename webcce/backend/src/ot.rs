@@ -0,0 +1,423 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+// --- Operational transformation for plain text ---
+//
+// Operations use the same JSON shape as ot.js: an array where a positive
+// number retains that many characters, a negative number deletes that many,
+// and a string inserts it. Lengths are counted in UTF-16 code units so they
+// line up with JavaScript string offsets in the browser.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Component {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<RawComponent>", into = "Vec<RawComponent>")]
+pub struct TextOperation {
+    ops: Vec<Component>,
+    base_len: usize,
+    target_len: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OtError {
+    InvalidComponent,
+    BaseLengthMismatch { expected: usize, actual: usize },
+    IncompatibleOperations,
+    UnknownRevision(usize),
+    InvalidUtf16,
+}
+
+impl fmt::Display for OtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OtError::InvalidComponent => write!(f, "operation contains an invalid component"),
+            OtError::BaseLengthMismatch { expected, actual } => {
+                write!(f, "operation expects a document of length {} but it has length {}", expected, actual)
+            }
+            OtError::IncompatibleOperations => write!(f, "operations do not apply to the same document"),
+            OtError::UnknownRevision(revision) => write!(f, "revision {} is not known to the server", revision),
+            OtError::InvalidUtf16 => write!(f, "operation splits a surrogate pair"),
+        }
+    }
+}
+
+pub fn utf16_len(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+impl TextOperation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn retain(&mut self, n: usize) -> &mut Self {
+        if n == 0 {
+            return self;
+        }
+        self.base_len += n;
+        self.target_len += n;
+        match self.ops.last_mut() {
+            Some(Component::Retain(last)) => *last += n,
+            _ => self.ops.push(Component::Retain(n)),
+        }
+        self
+    }
+
+    // Inserts are kept in front of an adjacent delete so equivalent
+    // operations always have the same representation.
+    pub fn insert(&mut self, s: &str) -> &mut Self {
+        if s.is_empty() {
+            return self;
+        }
+        self.target_len += utf16_len(s);
+        match self.ops.as_mut_slice() {
+            [.., Component::Insert(last)] => last.push_str(s),
+            [.., Component::Insert(prev), Component::Delete(_)] => prev.push_str(s),
+            [.., Component::Delete(_)] => {
+                let delete = self.ops.pop().expect("slice pattern matched a last element");
+                self.ops.push(Component::Insert(s.to_string()));
+                self.ops.push(delete);
+            }
+            _ => self.ops.push(Component::Insert(s.to_string())),
+        }
+        self
+    }
+
+    pub fn delete(&mut self, n: usize) -> &mut Self {
+        if n == 0 {
+            return self;
+        }
+        self.base_len += n;
+        match self.ops.last_mut() {
+            Some(Component::Delete(last)) => *last += n,
+            _ => self.ops.push(Component::Delete(n)),
+        }
+        self
+    }
+
+    // Client frames can carry any numbers, so the lengths they add up to are
+    // checked before they're used instead of being allowed to overflow.
+    fn check_growth(&self, base: usize, target: usize) -> Result<(), OtError> {
+        match (self.base_len.checked_add(base), self.target_len.checked_add(target)) {
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err(OtError::InvalidComponent),
+        }
+    }
+
+    pub fn apply(&self, doc: &str) -> Result<String, OtError> {
+        let units: Vec<u16> = doc.encode_utf16().collect();
+        if units.len() != self.base_len {
+            return Err(OtError::BaseLengthMismatch { expected: self.base_len, actual: units.len() });
+        }
+        let mut out: Vec<u16> = Vec::with_capacity(self.target_len);
+        let mut index = 0;
+        for component in &self.ops {
+            match component {
+                Component::Retain(n) => {
+                    out.extend_from_slice(&units[index..index + n]);
+                    index += n;
+                }
+                Component::Insert(s) => out.extend(s.encode_utf16()),
+                Component::Delete(n) => index += n,
+            }
+        }
+        String::from_utf16(&out).map_err(|_| OtError::InvalidUtf16)
+    }
+
+    // Where `position` in the old document ends up after applying this
+    // operation. Text inserted exactly at the position pushes it forward.
+    pub fn transform_position(&self, position: usize) -> usize {
+        let mut remaining = position as isize;
+        let mut new_position = position;
+        for component in &self.ops {
+            match component {
+                Component::Retain(n) => remaining -= *n as isize,
+                Component::Insert(s) => new_position += utf16_len(s),
+                Component::Delete(n) => {
+                    new_position -= (*n).min(remaining as usize);
+                    remaining -= *n as isize;
+                }
+            }
+            if remaining < 0 {
+                break;
+            }
+        }
+        new_position
+    }
+}
+
+// Transforms two concurrent operations `a` and `b` (both based on the same
+// document) into `(a', b')` such that applying `a` then `b'` gives the same
+// result as applying `b` then `a'`. When both insert at the same position,
+// `a`'s insert goes first.
+pub fn transform(a: &TextOperation, b: &TextOperation) -> Result<(TextOperation, TextOperation), OtError> {
+    if a.base_len != b.base_len {
+        return Err(OtError::IncompatibleOperations);
+    }
+
+    let mut a_prime = TextOperation::new();
+    let mut b_prime = TextOperation::new();
+    let mut ops_a = a.ops.iter().cloned();
+    let mut ops_b = b.ops.iter().cloned();
+    let mut op1 = ops_a.next();
+    let mut op2 = ops_b.next();
+
+    loop {
+        match (&op1, &op2) {
+            (None, None) => break,
+            (Some(Component::Insert(s)), _) => {
+                a_prime.insert(s);
+                b_prime.retain(utf16_len(s));
+                op1 = ops_a.next();
+                continue;
+            }
+            (_, Some(Component::Insert(s))) => {
+                a_prime.retain(utf16_len(s));
+                b_prime.insert(s);
+                op2 = ops_b.next();
+                continue;
+            }
+            (None, _) | (_, None) => return Err(OtError::IncompatibleOperations),
+            _ => {}
+        }
+
+        let (c1, c2) = (op1.take().expect("checked above"), op2.take().expect("checked above"));
+        let n = span(&c1).min(span(&c2));
+        match (&c1, &c2) {
+            (Component::Retain(_), Component::Retain(_)) => {
+                a_prime.retain(n);
+                b_prime.retain(n);
+            }
+            // Both deleted the same text: nothing left to do for either side.
+            (Component::Delete(_), Component::Delete(_)) => {}
+            (Component::Delete(_), Component::Retain(_)) => {
+                a_prime.delete(n);
+            }
+            (Component::Retain(_), Component::Delete(_)) => {
+                b_prime.delete(n);
+            }
+            _ => unreachable!("inserts are handled above"),
+        }
+        op1 = shorten(c1, n).or_else(|| ops_a.next());
+        op2 = shorten(c2, n).or_else(|| ops_b.next());
+    }
+
+    Ok((a_prime, b_prime))
+}
+
+fn span(component: &Component) -> usize {
+    match component {
+        Component::Retain(n) | Component::Delete(n) => *n,
+        Component::Insert(s) => utf16_len(s),
+    }
+}
+
+// What's left of a retain or delete after consuming `n` of it, if anything.
+fn shorten(component: Component, n: usize) -> Option<Component> {
+    match component {
+        Component::Retain(len) if len > n => Some(Component::Retain(len - n)),
+        Component::Delete(len) if len > n => Some(Component::Delete(len - n)),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawComponent {
+    Number(i64),
+    Text(String),
+}
+
+impl TryFrom<Vec<RawComponent>> for TextOperation {
+    type Error = OtError;
+
+    fn try_from(raw: Vec<RawComponent>) -> Result<Self, Self::Error> {
+        let mut operation = TextOperation::new();
+        for component in raw {
+            match component {
+                RawComponent::Number(n) if n > 0 => {
+                    operation.check_growth(n as usize, n as usize)?;
+                    operation.retain(n as usize)
+                }
+                RawComponent::Number(n) if n < 0 => {
+                    operation.check_growth(n.unsigned_abs() as usize, 0)?;
+                    operation.delete(n.unsigned_abs() as usize)
+                }
+                RawComponent::Text(s) if !s.is_empty() => {
+                    operation.check_growth(0, utf16_len(&s))?;
+                    operation.insert(&s)
+                }
+                _ => return Err(OtError::InvalidComponent),
+            };
+        }
+        Ok(operation)
+    }
+}
+
+impl From<TextOperation> for Vec<RawComponent> {
+    fn from(operation: TextOperation) -> Self {
+        operation
+            .ops
+            .into_iter()
+            .map(|component| match component {
+                Component::Retain(n) => RawComponent::Number(n as i64),
+                Component::Delete(n) => RawComponent::Number(-(n as i64)),
+                Component::Insert(s) => RawComponent::Text(s),
+            })
+            .collect()
+    }
+}
+
+// The server's copy of a document being edited. Operations the server accepts
+// are kept so that operations based on an older revision can be transformed
+// against everything that happened since, until no client can still send one.
+pub struct Document {
+    pub content: String,
+    // The operations that produced revisions `base + 1` onwards.
+    history: VecDeque<TextOperation>,
+    base: usize,
+}
+
+impl Document {
+    pub fn new(content: String) -> Self {
+        Document { content, history: VecDeque::new(), base: 0 }
+    }
+
+    // Number of operations applied so far.
+    pub fn revision(&self) -> usize {
+        self.base + self.history.len()
+    }
+
+    // Applies an operation the client based on `revision` and returns the
+    // transformed operation that was actually applied, for broadcasting.
+    pub fn receive(&mut self, revision: usize, operation: TextOperation) -> Result<TextOperation, OtError> {
+        if revision < self.base || revision > self.revision() {
+            return Err(OtError::UnknownRevision(revision));
+        }
+        let mut operation = operation;
+        for concurrent in self.history.range(revision - self.base..) {
+            operation = transform(&operation, concurrent)?.0;
+        }
+        self.content = operation.apply(&self.content)?;
+        self.history.push_back(operation.clone());
+        Ok(operation)
+    }
+
+    // Forgets the operations before `revision`; edits based on an older
+    // revision are rejected from then on.
+    pub fn forget_before(&mut self, revision: usize) {
+        let count = revision.saturating_sub(self.base).min(self.history.len());
+        self.history.drain(..count);
+        self.base += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(json: &str) -> TextOperation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // Applies a then b' and b then a' and checks both orders agree.
+    fn converge(doc: &str, a: &TextOperation, b: &TextOperation) -> String {
+        let (a_prime, b_prime) = transform(a, b).unwrap();
+        let ab = b_prime.apply(&a.apply(doc).unwrap()).unwrap();
+        let ba = a_prime.apply(&b.apply(doc).unwrap()).unwrap();
+        assert_eq!(ab, ba);
+        ab
+    }
+
+    #[test]
+    fn transform_converges() {
+        assert_eq!(converge("hello", &op(r#"[5," world"]"#), &op(r#"[-1,"J",4]"#)), "Jello world");
+        assert_eq!(converge("hello", &op("[1,-3,1]"), &op("[2,-2,1]")), "ho");
+        assert_eq!(converge("hello", &op(r#"[-5,"bye"]"#), &op(r#"[2,"!",3]"#)), "bye!");
+    }
+
+    #[test]
+    fn transform_puts_the_first_insert_first_on_a_tie() {
+        assert_eq!(converge("xy", &op(r#"[1,"A",1]"#), &op(r#"[1,"B",1]"#)), "xABy");
+        assert_eq!(converge("xy", &op(r#"[1,"B",1]"#), &op(r#"[1,"A",1]"#)), "xBAy");
+    }
+
+    #[test]
+    fn transform_rejects_operations_on_different_documents() {
+        assert_eq!(transform(&op("[3]"), &op("[4]")), Err(OtError::IncompatibleOperations));
+    }
+
+    #[test]
+    fn receive_transforms_against_concurrent_history() {
+        let mut document = Document::new("abc".to_string());
+        document.receive(0, op(r#"["X",3]"#)).unwrap();
+        document.receive(1, op(r#"[4,"Y"]"#)).unwrap();
+        // Based on revision 0, so it has to skip over both inserts.
+        let applied = document.receive(0, op("[2,-1]")).unwrap();
+        assert_eq!(applied, op(r#"[3,-1,1]"#));
+        assert_eq!(document.content, "XabY");
+        assert_eq!(document.revision(), 3);
+    }
+
+    #[test]
+    fn receive_rejects_revisions_it_does_not_have() {
+        let mut document = Document::new("abc".to_string());
+        document.receive(0, op(r#"["X",3]"#)).unwrap();
+        document.receive(1, op(r#"["Y",4]"#)).unwrap();
+        document.forget_before(2);
+        assert_eq!(document.receive(1, op("[4,-1]")), Err(OtError::UnknownRevision(1)));
+        assert_eq!(document.receive(3, op("[5,-1]")), Err(OtError::UnknownRevision(3)));
+        assert_eq!(document.receive(2, op("[4,-1]")).unwrap(), op("[4,-1]"));
+        assert_eq!(document.content, "YXab");
+    }
+
+    #[test]
+    fn transform_position_moves_with_deletes_and_inserts() {
+        let delete = op("[1,-3,2]");
+        assert_eq!(delete.transform_position(0), 0);
+        assert_eq!(delete.transform_position(2), 1);
+        assert_eq!(delete.transform_position(4), 1);
+        assert_eq!(delete.transform_position(6), 3);
+        // Text inserted exactly at the position pushes it forward.
+        assert_eq!(op(r#"[2,"ab",1]"#).transform_position(2), 4);
+    }
+
+    #[test]
+    fn apply_rejects_splitting_a_surrogate_pair() {
+        // "😀" is two UTF-16 code units.
+        assert_eq!(op("[2,-1,1]").apply("a😀b"), Err(OtError::InvalidUtf16));
+        assert_eq!(op(r#"[2,"x",2]"#).apply("a😀b"), Err(OtError::InvalidUtf16));
+        assert_eq!(op("[1,-2,1]").apply("a😀b").unwrap(), "ab");
+
+        let mut document = Document::new("a😀b".to_string());
+        assert_eq!(document.receive(0, op("[2,-1,1]")), Err(OtError::InvalidUtf16));
+        assert_eq!(document.content, "a😀b");
+        assert_eq!(document.revision(), 0);
+    }
+
+    #[test]
+    fn wire_format_round_trips() {
+        let json = r#"[3,"hi",-2,1]"#;
+        assert_eq!(serde_json::to_string(&op(json)).unwrap(), json);
+        // Adjacent components of the same kind are merged.
+        assert_eq!(serde_json::to_string(&op(r#"[1,2,"a","b",-1,-1]"#)).unwrap(), r#"[3,"ab",-2]"#);
+    }
+
+    #[test]
+    fn wire_format_rejects_invalid_components() {
+        for json in ["[0]", r#"[""]"#, "[1.5]", "[null]"] {
+            assert!(serde_json::from_str::<TextOperation>(json).is_err(), "{} was accepted", json);
+        }
+        // Lengths that would overflow are rejected rather than wrapping.
+        let huge = "4000000000000000000";
+        for json in [format!("[{0},{0},{0},{0},{0}]", huge), format!("[-{0},-{0},-{0},-{0},-{0}]", huge)] {
+            assert!(serde_json::from_str::<TextOperation>(&json).is_err(), "{} was accepted", json);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Json, Redirect, Response},
+};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::session::AuthUser;
+use crate::state::AppState;
+
+// Previews run the project's own scripts, so they are sandboxed into an
+// opaque origin: they can't read the API's cookies or storage, or call it
+// as the user.
+const PREVIEW_CSP: &str = "sandbox allow-scripts allow-forms allow-modals allow-popups";
+
+#[derive(Serialize)]
+pub struct PreviewTokenResponse {
+    token: String,
+    expires_at: u64,
+}
+
+// Handler for issuing a token that opens the preview of one project. The
+// token goes in the preview's URL, so pages can load their files by relative
+// paths without the session token ever reaching the preview.
+pub async fn issue_preview_token(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(project_id): Path<i32>,
+) -> Response {
+    info!("[preview] ==> API call to issue_preview_token for project {} by user '{}'", project_id, user.username);
+    {
+        let file_system = app_state.file_system.lock().await;
+        if !file_system.get(&user.room_id).is_some_and(|projects| projects.iter().any(|p| p.id == project_id)) {
+            info!("[preview] <== FAILURE: Project {} not found in room '{}'.", project_id, user.room_id);
+            return (StatusCode::NOT_FOUND, "Project not found").into_response();
+        }
+    }
+    let (token, claims) = app_state.sessions.issue_preview(&user.username, &user.room_id, project_id);
+    info!("[preview] <== SUCCESS: Issued a preview token for project {}.", project_id);
+    Json(PreviewTokenResponse { token, expires_at: claims.exp }).into_response()
+}
+
+// Serves a project as a static site, so the preview iframe can load pages,
+// scripts, styles and other files by their relative paths. Files that are
+// open in the editor are served with their live, unsaved content.
+pub async fn preview_file(
+    State(app_state): State<AppState>,
+    Path((token, path)): Path<(String, String)>,
+    uri: Uri,
+) -> Response {
+    serve(app_state, token, path, uri).await
+}
+
+// The project root. Without a trailing slash, relative links in index.html
+// would resolve against the parent directory, so redirect to add one.
+pub async fn preview_root(State(app_state): State<AppState>, Path(token): Path<String>, uri: Uri) -> Response {
+    if !uri.path().ends_with('/') {
+        return Redirect::temporary(&with_trailing_slash(&uri)).into_response();
+    }
+    serve(app_state, token, String::new(), uri).await
+}
+
+async fn serve(app_state: AppState, token: String, path: String, uri: Uri) -> Response {
+    let Some(claims) = app_state.sessions.verify_preview(&token) else {
+        info!("[preview] <== FAILURE: Invalid or expired preview token.");
+        return (StatusCode::UNAUTHORIZED, "Invalid or expired preview link").into_response();
+    };
+    let (room_id, project_id) = (claims.room_id, claims.project_id);
+    info!("[preview] ==> Preview of '{}' in project {} by user '{}'", path, project_id, claims.username);
+
+    let mut path = path.trim_start_matches('/').to_string();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+
+    // Lock order: the file system first, then the room manager.
+    let file_system = app_state.file_system.lock().await;
+    let Some(project) = file_system.get(&room_id).and_then(|projects| projects.iter().find(|p| p.id == project_id)) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let Some(file) = project.files.iter().find(|f| f.path() == path) else {
+        if project.has_folder(&path) {
+            return Redirect::temporary(&with_trailing_slash(&uri)).into_response();
+        }
+        info!("[preview] <== FAILURE: '{}' not found in project {}.", path, project_id);
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+    let content = if file.binary {
+        match app_state.storage.read_file(&room_id, file.id) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("[preview] <== FAILURE: Could not read file {}: {}", file.id, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Could not read file").into_response();
+            }
+        }
+    } else {
+        let content = {
+            let room_manager = app_state.room_manager.lock().await;
+            room_manager.get(&file.id).map(|room| room.document.content.clone())
+        }
+        .unwrap_or_else(|| file.content.clone());
+        if is_html(&path) { inject_live_reload(content) } else { content }.into_bytes()
+    };
+
+    info!("[preview] <== SUCCESS: Served '{}' from project {}.", path, project_id);
+    (
+        [
+            (header::CONTENT_TYPE, content_type(&path)),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (header::CONTENT_SECURITY_POLICY, PREVIEW_CSP.to_string()),
+        ],
+        content,
+    )
+        .into_response()
+}
+
+// The editor relays the project's change events to the preview with
+// postMessage. Stylesheets are swapped in place; anything else reloads.
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+window.addEventListener('message', function (event) {
+  if (event.source !== window.parent || !event.data || event.data.type !== 'webcce:file-changed') return;
+  var path = event.data.path || '';
+  if (/\.css$/i.test(path)) {
+    var links = document.querySelectorAll('link[rel="stylesheet"]');
+    var swapped = false;
+    for (var i = 0; i < links.length; i++) {
+      var url = new URL(links[i].href, location.href);
+      if (url.pathname.endsWith('/' + path)) {
+        url.searchParams.set('v', Date.now());
+        links[i].href = url.toString();
+        swapped = true;
+      }
+    }
+    if (swapped) return;
+  }
+  location.reload();
+});
+</script>"#;
+
+fn is_html(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".html") || path.ends_with(".htm")
+}
+
+// Injects the live-reload script before </body>, or at the end of pages
+// without one.
+fn inject_live_reload(mut html: String) -> String {
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(at) => html.insert_str(at, LIVE_RELOAD_SCRIPT),
+        None => html.push_str(LIVE_RELOAD_SCRIPT),
+    }
+    html
+}
+
+pub fn content_type(path: &str) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    if mime.type_() == mime_guess::mime::TEXT || mime.subtype() == mime_guess::mime::JAVASCRIPT || mime.subtype() == mime_guess::mime::JSON {
+        format!("{}; charset=utf-8", mime.essence_str())
+    } else {
+        mime.essence_str().to_string()
+    }
+}
+
+fn with_trailing_slash(uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) => format!("{}/?{}", uri.path(), query),
+        None => format!("{}/", uri.path()),
+    }
+}
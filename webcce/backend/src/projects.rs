@@ -0,0 +1,198 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use crate::files::update_projects;
+use crate::events::ProjectEvent;
+use crate::session::AuthUser;
+use crate::state::{self, AppState, Project};
+use crate::ws::close_deleted_rooms;
+use tracing::{error, info};
+
+#[derive(Deserialize)]
+pub struct CreateProjectRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct RenameProjectRequest {
+    id: i32,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateRoomRequest {
+    room_id: String,
+}
+
+// A project without its files, as returned by the project endpoints
+#[derive(Serialize)]
+pub struct ProjectSummary {
+    id: i32,
+    name: String,
+    file_count: usize,
+}
+
+impl From<&Project> for ProjectSummary {
+    fn from(project: &Project) -> Self {
+        ProjectSummary { id: project.id, name: project.name.clone(), file_count: project.files.len() }
+    }
+}
+
+pub fn validate_project_name(name: &str) -> Result<(), &'static str> {
+    if name.trim().is_empty() {
+        return Err("Project name cannot be empty");
+    }
+    if name.chars().count() > 100 {
+        return Err("Project name is too long");
+    }
+    if name.chars().any(|c| c.is_control()) {
+        return Err("Project name contains invalid characters");
+    }
+    Ok(())
+}
+
+pub fn validate_room_id(room_id: &str) -> Result<(), &'static str> {
+    if room_id.trim().is_empty() {
+        return Err("Room ID cannot be empty");
+    }
+    if room_id.chars().count() > 64 {
+        return Err("Room ID is too long");
+    }
+    if room_id.chars().any(|c| c.is_control()) {
+        return Err("Room ID contains invalid characters");
+    }
+    Ok(())
+}
+
+pub async fn list_projects(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+) -> Response {
+    info!("[projects] ==> API call to list_projects in room '{}' by user '{}'", user.room_id, user.username);
+    let file_system = app_state.file_system.lock().await;
+    match file_system.get(&user.room_id) {
+        Some(projects) => {
+            let summaries: Vec<ProjectSummary> = projects.iter().map(ProjectSummary::from).collect();
+            info!("[projects] <== SUCCESS: {} projects.", summaries.len());
+            Json(summaries).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+    }
+}
+
+pub async fn create_project(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<CreateProjectRequest>,
+) -> Response {
+    info!("[projects] ==> API call to create_project '{}' in room '{}' by user '{}'", payload.name, user.room_id, user.username);
+    if let Err(message) = validate_project_name(&payload.name) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let mut file_system = app_state.file_system.lock().await;
+    if let Err(e) = state::ensure_room(&mut file_system, &app_state.storage, &user.room_id) {
+        error!("[projects] <== FAILURE: Could not create room '{}': {}", user.room_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Could not save changes").into_response();
+    }
+    let projects = file_system.get_mut(&user.room_id).expect("room was just ensured");
+    if projects.iter().any(|p| p.name == payload.name) {
+        return (StatusCode::CONFLICT, "A project with that name already exists").into_response();
+    }
+
+    let project = Project { id: state::allocate_project_id(), name: payload.name, folders: Vec::new(), files: Vec::new() };
+    let summary = ProjectSummary::from(&project);
+    if let Err(status) = update_projects(&app_state, &user.room_id, projects, |projects| projects.push(project)) {
+        return (status, "Could not save changes").into_response();
+    }
+    info!("[projects] <== SUCCESS: Created project '{}' with id {}.", summary.name, summary.id);
+    (StatusCode::CREATED, Json(summary)).into_response()
+}
+
+pub async fn rename_project(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<RenameProjectRequest>,
+) -> Response {
+    info!("[projects] ==> API call to rename_project {} to '{}' by user '{}'", payload.id, payload.name, user.username);
+    if let Err(message) = validate_project_name(&payload.name) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    if projects.iter().any(|p| p.id != payload.id && p.name == payload.name) {
+        return (StatusCode::CONFLICT, "A project with that name already exists").into_response();
+    }
+    let Some(index) = projects.iter().position(|p| p.id == payload.id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let renamed = update_projects(&app_state, &user.room_id, projects, |projects| {
+        projects[index].name = payload.name;
+        ProjectSummary::from(&projects[index])
+    });
+    let summary = match renamed {
+        Ok(summary) => summary,
+        Err(status) => return (status, "Could not save changes").into_response(),
+    };
+    info!("[projects] <== SUCCESS: Renamed project {} to '{}'.", summary.id, summary.name);
+    Json(summary).into_response()
+}
+
+pub async fn delete_project(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(project_id): Path<i32>,
+) -> Response {
+    info!("[projects] ==> API call to delete_project {} by user '{}'", project_id, user.username);
+    let mut file_system = app_state.file_system.lock().await;
+    let Some(projects) = file_system.get_mut(&user.room_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let Some(index) = projects.iter().position(|p| p.id == project_id) else {
+        return (StatusCode::NOT_FOUND, "Project not found").into_response();
+    };
+    let project = match update_projects(&app_state, &user.room_id, projects, |projects| projects.remove(index)) {
+        Ok(project) => project,
+        Err(status) => return (status, "Could not save changes").into_response(),
+    };
+    close_deleted_rooms(&app_state, project.files.iter().map(|f| f.id)).await;
+    for file in &project.files {
+        if let Err(e) = app_state.storage.delete_file(&user.room_id, file.id) {
+            error!("[projects] Could not remove content of file {}: {}", file.id, e);
+        }
+    }
+    app_state.project_events.publish(project.id, ProjectEvent::Tree);
+    info!("[projects] <== SUCCESS: Deleted project '{}' and {} files.", project.name, project.files.len());
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// Creates an empty workspace. Signing up creates the user's room already, so
+// this needs a session: the new room only becomes usable once someone signs
+// up or logs in with its id.
+pub async fn create_room(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<CreateRoomRequest>,
+) -> Response {
+    info!("[projects] ==> API call to create_room '{}' by user '{}'", payload.room_id, user.username);
+    if let Err(message) = validate_room_id(&payload.room_id) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let mut file_system = app_state.file_system.lock().await;
+    match state::ensure_room(&mut file_system, &app_state.storage, &payload.room_id) {
+        Ok(true) => (StatusCode::CREATED, "Room created").into_response(),
+        Ok(false) => (StatusCode::CONFLICT, "Room already exists").into_response(),
+        Err(e) => {
+            error!("[projects] <== FAILURE: Could not create room '{}': {}", payload.room_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Could not save changes").into_response()
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ot::{utf16_len, TextOperation};
+
+// --- WebSocket protocol ---
+//
+// Every frame is a JSON object with a "type" field. Positions and operations
+// are counted in UTF-16 code units, like JavaScript string offsets.
+//
+// Client -> server
+//   {"type":"join","version":1}                   optional; announces the protocol
+//                                                 version the client speaks
+//   {"type":"edit","revision":3,"operation":[2,"x",-1]}
+//                                                 an ot.rs operation based on `revision`
+//   {"type":"cursor","position":12}
+//   {"type":"selection","anchor":4,"head":12}
+//   {"type":"save"}                               persist the live document
+//   {"type":"ping","nonce":7,"revision":3}       nonce is optional and echoed back;
+//                                                 revision, also optional, is the latest
+//                                                 one the client has, so the server can
+//                                                 forget the operations before it
+//   {"type":"leave"}                              closes the connection
+//
+// Server -> client
+//   {"type":"snapshot","version":1,"revision":3,"content":"..."}
+//                                                 always the first message
+//   {"type":"presence","users":[{"username":"bob","color":"#e06c75",
+//     "selection":{"anchor":4,"head":12}}]}       everyone else in the file, sent
+//                                                 right after the snapshot; selection
+//                                                 is null until they move their cursor
+//   {"type":"join","username":"bob","color":"#e06c75"}
+//   {"type":"leave","username":"bob"}
+//   {"type":"ack","revision":4}                   the sender's edit became `revision`
+//   {"type":"edit","revision":4,"operation":[...],"username":"bob"}
+//                                                 someone else's edit, already
+//                                                 transformed onto revision - 1
+//   {"type":"cursor","username":"bob","position":12}
+//                                                 positions are relative to the
+//                                                 latest revision the server sent
+//   {"type":"selection","username":"bob","anchor":4,"head":12}
+//   {"type":"saved","revision":4,"username":"bob"}
+//                                                 username is null for autosaves
+//   {"type":"pong","nonce":7}
+//   {"type":"error","code":"invalid_message","message":"..."}
+//
+// Error codes: invalid_message, unsupported_version, invalid_operation,
+// out_of_range, save_failed, file_deleted. Only unsupported_version and
+// file_deleted close the connection.
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Larger frames are rejected before parsing.
+pub const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Join { version: u32 },
+    Edit { revision: usize, operation: TextOperation },
+    Cursor { position: usize },
+    Selection { anchor: usize, head: usize },
+    Save,
+    Ping {
+        #[serde(default)]
+        nonce: Option<u64>,
+        #[serde(default)]
+        revision: Option<usize>,
+    },
+    Leave,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage<'a> {
+    Snapshot { version: u32, revision: usize, content: &'a str },
+    Presence { users: Vec<PresenceUser<'a>> },
+    Join { username: &'a str, color: &'a str },
+    Leave { username: &'a str },
+    Ack { revision: usize },
+    Edit { revision: usize, operation: &'a TextOperation, username: &'a str },
+    Cursor { username: &'a str, position: usize },
+    Selection { username: &'a str, anchor: usize, head: usize },
+    Saved { revision: usize, username: Option<&'a str> },
+    Pong { nonce: Option<u64> },
+    Error { code: ErrorCode, message: String },
+}
+
+#[derive(Serialize)]
+pub struct PresenceUser<'a> {
+    pub username: &'a str,
+    pub color: &'a str,
+    pub selection: Option<Selection>,
+}
+
+// A cursor is a selection whose anchor and head are the same.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnsupportedVersion,
+    InvalidOperation,
+    OutOfRange,
+    SaveFailed,
+    FileDeleted,
+}
+
+#[derive(Debug)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ProtocolError { code, message: message.into() }
+    }
+
+    pub fn to_message(&self) -> ServerMessage<'static> {
+        ServerMessage::Error { code: self.code, message: self.message.clone() }
+    }
+}
+
+impl ClientMessage {
+    pub fn parse(text: &str) -> Result<Self, ProtocolError> {
+        if text.len() > MAX_MESSAGE_LEN {
+            return Err(ProtocolError::new(ErrorCode::InvalidMessage, "Message is too large"));
+        }
+        serde_json::from_str(text)
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidMessage, format!("Invalid message: {}", e)))
+    }
+
+    // Checks what can be checked without touching the document history;
+    // edits are validated when they are transformed and applied.
+    pub fn validate(&self, content: &str) -> Result<(), ProtocolError> {
+        match self {
+            ClientMessage::Join { version } if *version != PROTOCOL_VERSION => Err(ProtocolError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("Protocol version {} is not supported, the server speaks version {}", version, PROTOCOL_VERSION),
+            )),
+            ClientMessage::Cursor { position } => check_position(*position, content),
+            ClientMessage::Selection { anchor, head } => {
+                check_position(*anchor, content)?;
+                check_position(*head, content)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_position(position: usize, content: &str) -> Result<(), ProtocolError> {
+    let len = utf16_len(content);
+    if position > len {
+        return Err(ProtocolError::new(
+            ErrorCode::OutOfRange,
+            format!("Position {} is past the end of the document (length {})", position, len),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::files::{authorize_file, find_file, store_file_content, update_live_document};
+use crate::session::{now_secs, AuthUser};
+use crate::state::AppState;
+
+// A file keeps its MAX_REVISIONS newest revisions. Older ones are dropped
+// every COMPACT_EVERY saves rather than on each one.
+const MAX_REVISIONS: usize = 200;
+const COMPACT_EVERY: u32 = 50;
+// Autosaves by the same author within one AUTOSAVE_WINDOW_SECS window of the
+// clock share a revision, so a long editing session doesn't push everything
+// else out of the history.
+const AUTOSAVE_WINDOW_SECS: u64 = 10 * 60;
+
+// How a save came about. Autosaves may be folded into the previous revision.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SaveKind {
+    Explicit,
+    Autosave,
+}
+
+// One saved version of a file. Ids count up from 1 per file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Revision {
+    pub id: u32,
+    pub author: String,
+    pub timestamp: u64,
+    pub content: String,
+    #[serde(default)]
+    pub autosave: bool,
+}
+
+// A revision without its content, for listings
+#[derive(Serialize)]
+pub struct RevisionSummary {
+    id: u32,
+    author: String,
+    timestamp: u64,
+    size: usize,
+    autosave: bool,
+}
+
+impl From<&Revision> for RevisionSummary {
+    fn from(revision: &Revision) -> Self {
+        RevisionSummary {
+            id: revision.id,
+            author: revision.author.clone(),
+            timestamp: revision.timestamp,
+            size: revision.content.len(),
+            autosave: revision.autosave,
+        }
+    }
+}
+
+// Adds `content` to the file's history, unless it is the same as the latest
+// revision. An autosave replaces the latest revision instead if that was an
+// autosave by the same author in the same window. A failure is logged but
+// doesn't undo the save it belongs to.
+pub fn record_revision(app_state: &AppState, room_id: &str, file_id: i32, author: &str, content: &str, kind: SaveKind) {
+    let latest = match app_state.storage.last_revision(room_id, file_id) {
+        Ok(latest) => latest,
+        Err(e) => {
+            error!("[revisions] Could not read history of file {}: {}", file_id, e);
+            return;
+        }
+    };
+    if latest.as_ref().is_some_and(|latest| latest.content == content) {
+        return;
+    }
+    let timestamp = now_secs();
+    let coalesce = latest.as_ref().is_some_and(|latest| replaces(latest, author, timestamp, kind));
+    let revision = Revision {
+        id: latest.map_or(1, |latest| if coalesce { latest.id } else { latest.id + 1 }),
+        author: author.to_string(),
+        timestamp,
+        content: content.to_string(),
+        autosave: kind == SaveKind::Autosave,
+    };
+    if coalesce {
+        if let Err(e) = app_state.storage.replace_last_revision(room_id, file_id, &revision) {
+            error!("[revisions] Could not update revision {} of file {}: {}", revision.id, file_id, e);
+        }
+        return;
+    }
+    if let Err(e) = app_state.storage.append_revision(room_id, file_id, &revision) {
+        error!("[revisions] Could not record revision {} of file {}: {}", revision.id, file_id, e);
+        return;
+    }
+    if revision.id.is_multiple_of(COMPACT_EVERY) {
+        if let Err(e) = app_state.storage.keep_latest_revisions(room_id, file_id, MAX_REVISIONS) {
+            error!("[revisions] Could not drop old revisions of file {}: {}", file_id, e);
+        }
+    }
+}
+
+// True if a save by `author` at `timestamp` should overwrite `latest`
+// instead of being added after it.
+fn replaces(latest: &Revision, author: &str, timestamp: u64, kind: SaveKind) -> bool {
+    kind == SaveKind::Autosave
+        && latest.autosave
+        && latest.author == author
+        && latest.timestamp / AUTOSAVE_WINDOW_SECS == timestamp / AUTOSAVE_WINDOW_SECS
+}
+
+fn load_history(app_state: &AppState, room_id: &str, file_id: i32) -> Result<Vec<Revision>, StatusCode> {
+    app_state.storage.load_revisions(room_id, file_id).map_err(|e| {
+        error!("[revisions] Could not read history of file {}: {}", file_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+pub async fn list_revisions(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path(file_id): Path<i32>,
+) -> Response {
+    info!("[revisions] ==> API call to list_revisions for file_id: {} by user '{}'", file_id, user.username);
+    let file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    match load_history(&app_state, &user.room_id, file_id) {
+        Ok(history) => {
+            info!("[revisions] <== SUCCESS: {} revisions.", history.len());
+            Json(history.iter().map(RevisionSummary::from).collect::<Vec<_>>()).into_response()
+        }
+        Err(status) => (status, "Could not read revisions").into_response(),
+    }
+}
+
+pub async fn get_revision(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path((file_id, revision_id)): Path<(i32, u32)>,
+) -> Response {
+    info!("[revisions] ==> API call to get_revision {} of file_id: {} by user '{}'", revision_id, file_id, user.username);
+    let file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let history = match load_history(&app_state, &user.room_id, file_id) {
+        Ok(history) => history,
+        Err(status) => return (status, "Could not read revisions").into_response(),
+    };
+    match history.into_iter().find(|r| r.id == revision_id) {
+        Some(revision) => Json(revision).into_response(),
+        None => (StatusCode::NOT_FOUND, "Revision not found").into_response(),
+    }
+}
+
+// Makes an old revision the current content. The restore is saved as a new
+// revision, and anyone editing the file sees it as a normal edit.
+pub async fn restore_revision(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    Path((file_id, revision_id)): Path<(i32, u32)>,
+) -> Response {
+    info!("[revisions] ==> API call to restore_revision {} of file_id: {} by user '{}'", revision_id, file_id, user.username);
+    let mut file_system = app_state.file_system.lock().await;
+    if let Err(status) = authorize_file(&file_system, &user, file_id) {
+        return (status, "File not found or not in your room").into_response();
+    }
+    let history = match load_history(&app_state, &user.room_id, file_id) {
+        Ok(history) => history,
+        Err(status) => return (status, "Could not read revisions").into_response(),
+    };
+    let Some(revision) = history.into_iter().find(|r| r.id == revision_id) else {
+        return (StatusCode::NOT_FOUND, "Revision not found").into_response();
+    };
+    if let Err(status) =
+        store_file_content(&app_state, &mut file_system, &user.room_id, file_id, revision.content.clone(), &user.username, SaveKind::Explicit)
+    {
+        return (status, "Could not save changes").into_response();
+    }
+
+    update_live_document(&app_state, &file_system, &user.room_id, file_id, &user.username).await;
+
+    let name = find_file(&file_system, &user.room_id, file_id).map(|f| f.name.clone()).unwrap_or_default();
+    info!("[revisions] <== SUCCESS: Restored revision {} of file '{}'.", revision_id, name);
+    Json(RevisionSummary::from(&revision)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn autosave(author: &str, timestamp: u64) -> Revision {
+        Revision { id: 1, author: author.to_string(), timestamp, content: String::new(), autosave: true }
+    }
+
+    #[test]
+    fn autosaves_in_the_same_window_share_a_revision() {
+        let latest = autosave("alice", 1200);
+        assert!(replaces(&latest, "alice", 1200, SaveKind::Autosave));
+        assert!(replaces(&latest, "alice", 1799, SaveKind::Autosave));
+        assert!(!replaces(&latest, "alice", 1800, SaveKind::Autosave));
+    }
+
+    #[test]
+    fn other_authors_get_their_own_revision() {
+        assert!(!replaces(&autosave("alice", 1200), "bob", 1200, SaveKind::Autosave));
+        assert!(!replaces(&autosave("alice", 1200), "alice, bob", 1200, SaveKind::Autosave));
+    }
+
+    #[test]
+    fn explicit_saves_are_never_folded() {
+        let latest = autosave("alice", 1200);
+        assert!(!replaces(&latest, "alice", 1200, SaveKind::Explicit));
+        let explicit = Revision { autosave: false, ..latest };
+        assert!(!replaces(&explicit, "alice", 1200, SaveKind::Autosave));
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SESSION_COOKIE: &str = "webcce_session";
+const SESSION_TTL_SECS: u64 = 12 * 60 * 60;
+const PREVIEW_TTL_SECS: u64 = 60 * 60;
+// Preview tokens are signed with this prefix in front of the payload, so one
+// can never pass for a session token.
+const PREVIEW_DOMAIN: &[u8] = b"preview.";
+
+// The claims carried inside a session token.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Claims {
+    pub username: String,
+    pub room_id: String,
+    pub exp: u64,
+}
+
+// The claims carried inside a preview token. It opens one project's preview
+// and nothing else, so it is safe to put in the preview's URL.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreviewClaims {
+    pub username: String,
+    pub room_id: String,
+    pub project_id: i32,
+    pub exp: u64,
+}
+
+// Signs and verifies session and preview tokens of the form
+// `<payload>.<signature>`, both parts base64url-encoded. The payload is the
+// JSON of `Claims` or `PreviewClaims`.
+#[derive(Clone)]
+pub struct SessionKeys {
+    secret: Arc<Vec<u8>>,
+}
+
+impl SessionKeys {
+    // Reads SESSION_SECRET from the environment. Without it a random secret is
+    // generated, which means every restart logs all users out.
+    pub fn from_env() -> Self {
+        let secret = match std::env::var("SESSION_SECRET") {
+            Ok(s) if !s.is_empty() => s.into_bytes(),
+            _ => {
+                warn!("[session] SESSION_SECRET not set. Using a random secret; sessions will not survive restarts.");
+                let mut bytes = vec![0u8; 32];
+                OsRng.fill_bytes(&mut bytes);
+                bytes
+            }
+        };
+        SessionKeys { secret: Arc::new(secret) }
+    }
+
+    pub fn issue(&self, username: &str, room_id: &str) -> (String, Claims) {
+        let claims = Claims {
+            username: username.to_string(),
+            room_id: room_id.to_string(),
+            exp: now_secs() + SESSION_TTL_SECS,
+        };
+        (self.encode(b"", &claims), claims)
+    }
+
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        let claims: Claims = self.decode(b"", token)?;
+        (claims.exp > now_secs()).then_some(claims)
+    }
+
+    pub fn issue_preview(&self, username: &str, room_id: &str, project_id: i32) -> (String, PreviewClaims) {
+        let claims = PreviewClaims {
+            username: username.to_string(),
+            room_id: room_id.to_string(),
+            project_id,
+            exp: now_secs() + PREVIEW_TTL_SECS,
+        };
+        (self.encode(PREVIEW_DOMAIN, &claims), claims)
+    }
+
+    pub fn verify_preview(&self, token: &str) -> Option<PreviewClaims> {
+        let claims: PreviewClaims = self.decode(PREVIEW_DOMAIN, token)?;
+        (claims.exp > now_secs()).then_some(claims)
+    }
+
+    fn encode<T: Serialize>(&self, domain: &[u8], claims: &T) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("claims serialize"));
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(&[domain, payload.as_bytes()].concat()));
+        format!("{}.{}", payload, signature)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, domain: &[u8], token: &str) -> Option<T> {
+        let (payload, signature) = token.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let mut mac = self.mac();
+        mac.update(domain);
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+}
+
+pub fn session_cookie(token: &str) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        SESSION_COOKIE, token, SESSION_TTL_SECS
+    )
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Extractor for the authenticated caller. The token is looked up in the
+// `Authorization: Bearer` header, then the session cookie, and finally a
+// `token` query parameter for clients (like browser WebSockets) that cannot
+// set headers.
+pub struct AuthUser {
+    pub username: String,
+    pub room_id: String,
+}
+
+pub struct AuthRejection;
+
+impl IntoResponse for AuthRejection {
+    fn into_response(self) -> Response {
+        (StatusCode::UNAUTHORIZED, Json("Missing or invalid session token")).into_response()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    SessionKeys: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let keys = SessionKeys::from_ref(state);
+        let token = token_from_parts(parts).ok_or_else(|| {
+            info!("[session] Rejected {}: no session token.", parts.uri.path());
+            AuthRejection
+        })?;
+        let claims = keys.verify(&token).ok_or_else(|| {
+            info!("[session] Rejected {}: invalid or expired session token.", parts.uri.path());
+            AuthRejection
+        })?;
+        Ok(AuthUser { username: claims.username, room_id: claims.room_id })
+    }
+}
+
+fn token_from_parts(parts: &Parts) -> Option<String> {
+    if let Some(value) = parts.headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            return Some(token.trim().to_string());
+        }
+    }
+
+    for value in parts.headers.get_all(header::COOKIE) {
+        let Ok(value) = value.to_str() else { continue };
+        for pair in value.split(';') {
+            if let Some((name, token)) = pair.trim().split_once('=') {
+                if name == SESSION_COOKIE {
+                    return Some(token.to_string());
+                }
+            }
+        }
+    }
+
+    parts.uri.query()?.split('&').find_map(|pair| {
+        let (name, token) = pair.split_once('=')?;
+        (name == "token").then(|| token.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(secret: &str) -> SessionKeys {
+        SessionKeys { secret: Arc::new(secret.as_bytes().to_vec()) }
+    }
+
+    #[test]
+    fn issued_tokens_verify() {
+        let keys = keys("secret");
+        let (token, issued) = keys.issue("alice", "room1");
+        let claims = keys.verify(&token).unwrap();
+        assert_eq!(claims.username, "alice");
+        assert_eq!(claims.room_id, "room1");
+        assert_eq!(claims.exp, issued.exp);
+    }
+
+    #[test]
+    fn tokens_from_another_secret_are_rejected() {
+        let (token, _) = keys("secret").issue("alice", "room1");
+        assert!(keys("other").verify(&token).is_none());
+    }
+
+    #[test]
+    fn tampered_payloads_are_rejected() {
+        let keys = keys("secret");
+        let (token, _) = keys.issue("alice", "room1");
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = Claims { username: "alice".to_string(), room_id: "room2".to_string(), exp: now_secs() + 60 };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert!(keys.verify(&format!("{}.{}", payload, signature)).is_none());
+        assert!(keys.verify(&token.replace('.', "")).is_none());
+        assert!(keys.verify(&format!("{}x", token)).is_none());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let keys = keys("secret");
+        let expired = Claims { username: "alice".to_string(), room_id: "room1".to_string(), exp: now_secs() - 1 };
+        assert!(keys.verify(&keys.encode(b"", &expired)).is_none());
+        let expired = PreviewClaims { username: "alice".to_string(), room_id: "room1".to_string(), project_id: 1, exp: now_secs() - 1 };
+        assert!(keys.verify_preview(&keys.encode(PREVIEW_DOMAIN, &expired)).is_none());
+    }
+
+    #[test]
+    fn preview_and_session_tokens_are_not_interchangeable() {
+        let keys = keys("secret");
+        let (preview, _) = keys.issue_preview("alice", "room1", 3);
+        assert_eq!(keys.verify_preview(&preview).unwrap().project_id, 3);
+        assert!(keys.verify(&preview).is_none());
+        let (session, _) = keys.issue("alice", "room1");
+        assert!(keys.verify_preview(&session).is_none());
+    }
+}
@@ -1,74 +1,308 @@
-use axum::extract::ws::Message;
-use serde::Serialize;
+use axum::extract::{ws::Message, FromRef};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, atomic::{AtomicI32, Ordering}};
+use std::time::Instant;
 use tokio::sync::{Mutex, mpsc};
 use tracing::info;
 
+use crate::conversations::Conversation;
+use crate::events::ProjectEvents;
+use crate::llm::ChatProvider;
+use crate::ot::{Document, OtError, TextOperation};
+use crate::protocol::Selection;
+use crate::session::SessionKeys;
+use crate::storage::{Counters, Storage};
+use crate::users::UserStore;
+
 // --- In-Memory "Database" Structs ---
 
 // A unique ID for each file, generated in memory.
 static NEXT_FILE_ID: AtomicI32 = AtomicI32::new(1);
+// Likewise for projects. Both counters are persisted in counters.json.
+static NEXT_PROJECT_ID: AtomicI32 = AtomicI32::new(1);
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct File {
     pub id: i32,
     pub name: String,
-    #[serde(skip_serializing)]
+    // Path of the containing folder, "" for the project root.
+    #[serde(default)]
+    pub folder: String,
+    #[serde(skip_serializing, default)]
     pub content: String,
+    // Goes up by one whenever the content changes; clients send it back to
+    // make sure they are not saving over a newer version.
+    #[serde(default)]
+    pub version: u64,
+    // Uploaded images, fonts, audio and the like. Their bytes are only kept
+    // on disk and `content` stays empty.
+    #[serde(default)]
+    pub binary: bool,
+}
+
+impl File {
+    // The file's path relative to the project root, e.g. "css/style.css".
+    pub fn path(&self) -> String {
+        join_path(&self.folder, &self.name)
+    }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Project {
     pub id: i32,
     pub name: String,
+    // Every folder in the project by path ("css", "assets/img"), including
+    // empty ones. A folder's ancestors are always listed too.
+    #[serde(default)]
+    pub folders: Vec<String>,
     pub files: Vec<File>,
 }
 
+impl Project {
+    pub fn has_folder(&self, path: &str) -> bool {
+        path.is_empty() || self.folders.iter().any(|f| f == path)
+    }
+
+    // Adds a folder and any missing ancestors.
+    pub fn ensure_folder(&mut self, path: &str) {
+        let mut current = String::new();
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            current = join_path(&current, segment);
+            if !self.has_folder(&current) {
+                self.folders.push(current.clone());
+            }
+        }
+        self.folders.sort();
+    }
+
+    pub fn file_at(&self, folder: &str, name: &str) -> Option<&File> {
+        self.files.iter().find(|f| f.folder == folder && f.name == name)
+    }
+
+    // True if a file sits where `folder` or one of its ancestors would be,
+    // so nothing can be put inside it.
+    pub fn folder_blocked(&self, folder: &str) -> bool {
+        let mut current = folder;
+        while !current.is_empty() {
+            if self.files.iter().any(|f| f.path() == current) {
+                return true;
+            }
+            current = parent_path(current);
+        }
+        false
+    }
+}
+
+pub fn join_path(folder: &str, name: &str) -> String {
+    if folder.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", folder, name)
+    }
+}
+
+// The folder that contains `path`, "" for top-level entries.
+pub fn parent_path(path: &str) -> &str {
+    path.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("")
+}
+
+// True if `path` is `folder` itself or somewhere inside it.
+pub fn is_within(path: &str, folder: &str) -> bool {
+    path == folder || path.strip_prefix(folder).is_some_and(|rest| rest.starts_with('/'))
+}
+
 pub type FileSystem = Arc<Mutex<HashMap<String, Vec<Project>>>>;
 
+pub fn allocate_file_id() -> i32 {
+    NEXT_FILE_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+pub fn allocate_project_id() -> i32 {
+    NEXT_PROJECT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+pub fn counters() -> Counters {
+    Counters {
+        next_file_id: NEXT_FILE_ID.load(Ordering::SeqCst),
+        next_project_id: NEXT_PROJECT_ID.load(Ordering::SeqCst),
+    }
+}
+
+// Creates an empty room if it doesn't exist yet. Returns whether it was created.
+pub fn ensure_room(
+    file_system: &mut HashMap<String, Vec<Project>>,
+    storage: &Storage,
+    room_id: &str,
+) -> std::io::Result<bool> {
+    if file_system.contains_key(room_id) {
+        return Ok(false);
+    }
+    storage.save_manifest(room_id, &[])?;
+    file_system.insert(room_id.to_string(), Vec::new());
+    info!("[state] Created room '{}'.", room_id);
+    Ok(true)
+}
+
 
 #[allow(dead_code)]
 pub struct UserState {
     pub username: String,
     pub sender: mpsc::UnboundedSender<Message>,
+    pub color: &'static str,
+    // Last reported cursor or selection, kept in step with the document.
+    pub selection: Option<Selection>,
+    // The revision the connection joined at or last based an edit on. It
+    // only moves forward, so the connection never sends anything older.
+    pub revision: usize,
 }
 
 #[allow(dead_code)]
 pub struct Room {
-    pub users: HashMap<String, UserState>,
+    pub room_id: String,
+    // Keyed by connection id, so one user can have several tabs open.
+    pub connections: HashMap<u64, UserState>,
+    // The live document, starting from the file's saved content.
+    pub document: Document,
+    // The stored file version the document is based on. If the file moves
+    // past it without going through the room, the document is stale and
+    // must not be written back over the newer content.
+    pub file_version: u64,
+    // The revision last written back to the file, and when and by whom the
+    // edits since then were made.
+    pub saved_revision: usize,
+    pub unsaved_since: Option<Instant>,
+    pub last_edit: Option<Instant>,
+    pub unsaved_editors: Vec<String>,
+}
+
+impl Room {
+    pub fn new(room_id: &str, content: String, file_version: u64) -> Self {
+        Room {
+            room_id: room_id.to_string(),
+            connections: HashMap::new(),
+            document: Document::new(content),
+            file_version,
+            saved_revision: 0,
+            unsaved_since: None,
+            last_edit: None,
+            unsaved_editors: Vec::new(),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.document.revision() > self.saved_revision
+    }
+
+    // Applies an edit from `username` based on `revision` and moves everyone's
+    // cursor along with it. Returns the operation as applied, for broadcasting.
+    pub fn apply_edit(&mut self, revision: usize, operation: TextOperation, username: &str, now: Instant) -> Result<TextOperation, OtError> {
+        let operation = self.document.receive(revision, operation)?;
+        for user in self.connections.values_mut() {
+            if let Some(selection) = &mut user.selection {
+                selection.anchor = operation.transform_position(selection.anchor);
+                selection.head = operation.transform_position(selection.head);
+            }
+        }
+        self.unsaved_since.get_or_insert(now);
+        self.last_edit = Some(now);
+        if !self.unsaved_editors.iter().any(|u| u == username) {
+            self.unsaved_editors.push(username.to_string());
+        }
+        Ok(operation)
+    }
+
+    // Notes that `connection_id` has based an edit on `revision`.
+    pub fn acknowledge(&mut self, connection_id: u64, revision: usize) {
+        if let Some(user) = self.connections.get_mut(&connection_id) {
+            user.revision = user.revision.max(revision);
+        }
+    }
+
+    // Drops the operations no connected client can base an edit on any more.
+    pub fn trim_history(&mut self) {
+        let oldest = self.connections.values().map(|user| user.revision).min().unwrap_or(self.document.revision());
+        self.document.forget_before(oldest);
+    }
+
+    pub fn mark_saved(&mut self, revision: usize) {
+        self.saved_revision = self.saved_revision.max(revision);
+        if !self.is_dirty() {
+            self.unsaved_since = None;
+            self.unsaved_editors.clear();
+        }
+    }
 }
 
 pub type RoomManager = Arc<Mutex<HashMap<i32, Room>>>;
 
+// Chat conversations by username, read from disk on a user's first request.
+pub type Conversations = Arc<Mutex<HashMap<String, Vec<Conversation>>>>;
+
+// One lock per (username, conversation id), held for a whole chat turn so
+// turns in the same conversation run one after another.
+pub type ConversationTurns = Arc<Mutex<HashMap<(String, String), Arc<Mutex<()>>>>>;
+
 #[derive(Clone)]
 #[allow(dead_code)]
 pub struct AppState {
     pub file_system: FileSystem,
     pub room_manager: RoomManager,
-    pub openai_api_key: String,
+    pub chat_provider: Arc<dyn ChatProvider>,
+    pub conversations: Conversations,
+    pub conversation_turns: ConversationTurns,
+    pub sessions: SessionKeys,
+    pub user_store: Arc<dyn UserStore>,
+    pub storage: Arc<Storage>,
+    pub project_events: ProjectEvents,
+}
+
+impl FromRef<AppState> for SessionKeys {
+    fn from_ref(state: &AppState) -> Self {
+        state.sessions.clone()
+    }
 }
 
 
-pub fn create_initial_data() -> FileSystem {
-    info!("[state] ==> create_initial_data() called. Initializing in-memory file system.");
+// Loads the persisted file system, seeding the demo room on first boot.
+// NEXT_FILE_ID is restored before anything can allocate a new id.
+pub fn load_file_system(storage: &Storage) -> FileSystem {
+    info!("[state] ==> load_file_system() called.");
+
+    let counters = storage.load_counters().expect("failed to read id counters");
+    let mut fs = storage.load_rooms().expect("failed to load persisted rooms");
+    let max_file_id = fs.values().flatten().flat_map(|p| &p.files).map(|f| f.id).max().unwrap_or(0);
+    NEXT_FILE_ID.fetch_max(counters.next_file_id.max(max_file_id + 1), Ordering::SeqCst);
+    let max_project_id = fs.values().flatten().map(|p| p.id).max().unwrap_or(0);
+    NEXT_PROJECT_ID.fetch_max(counters.next_project_id.max(max_project_id + 1), Ordering::SeqCst);
+
+    if fs.is_empty() {
+        fs = create_initial_data();
+        for (room_id, projects) in &fs {
+            storage.save_room(room_id, projects).expect("failed to persist initial data");
+        }
+    }
+
+    let keys: Vec<_> = fs.keys().cloned().collect();
+    info!("[state] <== File system ready with rooms: {:?}. Next file id: {}", keys, NEXT_FILE_ID.load(Ordering::SeqCst));
+    Arc::new(Mutex::new(fs))
+}
+
+fn create_initial_data() -> HashMap<String, Vec<Project>> {
+    info!("[state] ==> create_initial_data() called. Seeding the demo room.");
 
     let mut fs = HashMap::new();
     
-    let html_file = File { id: NEXT_FILE_ID.fetch_add(1, Ordering::SeqCst), name: "index.html".to_string(), content: "<h1>Hello</h1>".to_string() };
-    let css_file = File { id: NEXT_FILE_ID.fetch_add(1, Ordering::SeqCst), name: "style.css".to_string(), content: "h1 { color: blue; }".to_string() };
-    let js_file = File { id: NEXT_FILE_ID.fetch_add(1, Ordering::SeqCst), name: "script.js".to_string(), content: "console.log('hello')".to_string() };
-    let demo_project = Project { id: 1, name: "Demo Website".to_string(), files: vec![html_file, css_file, js_file] };
+    let html_file = File { id: allocate_file_id(), name: "index.html".to_string(), folder: String::new(), content: "<h1>Hello</h1>".to_string(), version: 1, binary: false };
+    let css_file = File { id: allocate_file_id(), name: "style.css".to_string(), folder: String::new(), content: "h1 { color: blue; }".to_string(), version: 1, binary: false };
+    let js_file = File { id: allocate_file_id(), name: "script.js".to_string(), folder: String::new(), content: "console.log('hello')".to_string(), version: 1, binary: false };
+    let demo_project = Project { id: allocate_project_id(), name: "Demo Website".to_string(), folders: Vec::new(), files: vec![html_file, css_file, js_file] };
     
-    let py_file = File { id: NEXT_FILE_ID.fetch_add(1, Ordering::SeqCst), name: "python.py".to_string(), content: "# PYTHON".to_string() };
-    let another_project = Project { id: 2, name: "Another Project".to_string(), files: vec![py_file] };
+    let py_file = File { id: allocate_file_id(), name: "python.py".to_string(), folder: String::new(), content: "# PYTHON".to_string(), version: 1, binary: false };
+    let another_project = Project { id: allocate_project_id(), name: "Another Project".to_string(), folders: Vec::new(), files: vec![py_file] };
 
     fs.insert("public_room".to_string(), vec![demo_project, another_project]);
     
-    // NEW! Extremely important log to confirm the data is there.
-    let keys: Vec<_> = fs.keys().cloned().collect();
-    info!("[state] Inserted data. In-memory file system now contains rooms: {:?}", keys);
-    
     info!("[state] <== Initial data creation complete.");
-    Arc::new(Mutex::new(fs))
+    fs
 }
\ No newline at end of file
@@ -0,0 +1,449 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::conversations::Conversation;
+use crate::revisions::Revision;
+use crate::state::{self, File, Project};
+
+const DATA_DIR: &str = "data";
+// How much of a revision history is read at a time when looking for its
+// last line.
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+// --- On-disk layout ---
+//
+// <data dir>/
+//   counters.json                     id counters that must never go backwards
+//   rooms/<room>/manifest.json        the room's projects and file metadata
+//   rooms/<room>/files/<file id>      raw file contents, text or binary
+//   rooms/<room>/revisions/<file id>.jsonl
+//                                     the file's recent saved versions, one
+//                                     JSON object per line, oldest first
+//   git/<room>/                       git repositories exported from the
+//                                     room's projects, and repositories or
+//                                     bundles placed there to be imported
+//   conversations/<user>/<id>.json    a user's chats with the assistant
+//
+// Room and user directory names are the room id or username with anything
+// outside [A-Za-z0-9_-] percent-encoded; the real room id is kept in the
+// manifest.
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Counters {
+    pub next_file_id: i32,
+    #[serde(default)]
+    pub next_project_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    room_id: String,
+    projects: Vec<Project>,
+}
+
+pub struct Storage {
+    root: PathBuf,
+}
+
+impl Storage {
+    // Uses WEBCCE_DATA_DIR, or ./data when it isn't set.
+    pub fn from_env() -> Self {
+        let root = std::env::var("WEBCCE_DATA_DIR").unwrap_or_else(|_| DATA_DIR.to_string());
+        info!("[storage] Using data directory '{}'.", root);
+        Storage::new(root)
+    }
+
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Storage { root: root.into() }
+    }
+
+    pub fn load_counters(&self) -> io::Result<Counters> {
+        match fs::read(self.root.join("counters.json")) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(invalid_data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Counters::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_counters(&self, counters: &Counters) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(counters).map_err(invalid_data)?;
+        write_atomic(&self.root.join("counters.json"), &json)
+    }
+
+    // Loads every room found on disk, including file contents. Returns an
+    // empty map when nothing has been persisted yet.
+    pub fn load_rooms(&self) -> io::Result<HashMap<String, Vec<Project>>> {
+        let mut rooms = HashMap::new();
+        let rooms_dir = self.root.join("rooms");
+        let entries = match fs::read_dir(&rooms_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(rooms),
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries {
+            let dir = entry?.path();
+            let manifest_path = dir.join("manifest.json");
+            if !manifest_path.is_file() {
+                continue;
+            }
+            let mut manifest: Manifest =
+                serde_json::from_slice(&fs::read(&manifest_path)?).map_err(invalid_data)?;
+            for project in &mut manifest.projects {
+                // Binary files are read from disk when they are served.
+                for file in project.files.iter_mut().filter(|f| !f.binary) {
+                    match fs::read_to_string(dir.join("files").join(file.id.to_string())) {
+                        Ok(content) => file.content = content,
+                        Err(e) => warn!("[storage] Missing content for file {} in room '{}': {}", file.id, manifest.room_id, e),
+                    }
+                }
+            }
+            rooms.insert(manifest.room_id, manifest.projects);
+        }
+        Ok(rooms)
+    }
+
+    // Writes the room's manifest and the content of every file in it.
+    pub fn save_room(&self, room_id: &str, projects: &[Project]) -> io::Result<()> {
+        for file in projects.iter().flat_map(|p| &p.files).filter(|f| !f.binary) {
+            self.save_file(room_id, file)?;
+        }
+        self.save_manifest(room_id, projects)
+    }
+
+    // Writes only the room's manifest (project and file metadata), along with
+    // the id counters so ids handed out since the last write are never reused.
+    pub fn save_manifest(&self, room_id: &str, projects: &[Project]) -> io::Result<()> {
+        let manifest = Manifest { room_id: room_id.to_string(), projects: projects.to_vec() };
+        let json = serde_json::to_vec_pretty(&manifest).map_err(invalid_data)?;
+        write_atomic(&self.room_dir(room_id).join("manifest.json"), &json)?;
+        self.save_counters(&state::counters())
+    }
+
+    pub fn save_file(&self, room_id: &str, file: &File) -> io::Result<()> {
+        write_atomic(&self.file_path(room_id, file.id), file.content.as_bytes())
+    }
+
+    pub fn save_bytes(&self, room_id: &str, file_id: i32, bytes: &[u8]) -> io::Result<()> {
+        write_atomic(&self.file_path(room_id, file_id), bytes)
+    }
+
+    pub fn read_file(&self, room_id: &str, file_id: i32) -> io::Result<Vec<u8>> {
+        fs::read(self.file_path(room_id, file_id))
+    }
+
+    // Removes the file's content and its revision history.
+    pub fn delete_file(&self, room_id: &str, file_id: i32) -> io::Result<()> {
+        remove_if_exists(&self.file_path(room_id, file_id))?;
+        remove_if_exists(&self.revisions_path(room_id, file_id))
+    }
+
+    pub fn append_revision(&self, room_id: &str, file_id: i32, revision: &Revision) -> io::Result<()> {
+        let path = self.revisions_path(room_id, file_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_vec(revision).map_err(invalid_data)?;
+        line.push(b'\n');
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+    }
+
+    // A line that doesn't parse (say, cut short by a crash) is skipped.
+    pub fn load_revisions(&self, room_id: &str, file_id: i32) -> io::Result<Vec<Revision>> {
+        let text = match fs::read_to_string(self.revisions_path(room_id, file_id)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(text
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(revision) => Some(revision),
+                Err(e) => {
+                    warn!("[storage] Skipping unreadable revision of file {} in room '{}': {}", file_id, room_id, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    // The newest revision. Only the end of the history is read, so saving
+    // stays cheap however long the history has grown.
+    pub fn last_revision(&self, room_id: &str, file_id: i32) -> io::Result<Option<Revision>> {
+        let mut file = match fs::File::open(self.revisions_path(room_id, file_id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (_, line) = last_line(&mut file)?;
+        if line.is_empty() {
+            return Ok(None);
+        }
+        match serde_json::from_slice(&line) {
+            Ok(revision) => Ok(Some(revision)),
+            // Cut short by a crash, say; the full read skips it.
+            Err(_) => Ok(self.load_revisions(room_id, file_id)?.pop()),
+        }
+    }
+
+    // Overwrites the newest revision, again without reading the whole history.
+    pub fn replace_last_revision(&self, room_id: &str, file_id: i32, revision: &Revision) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(self.revisions_path(room_id, file_id))?;
+        let (start, _) = last_line(&mut file)?;
+        let mut line = serde_json::to_vec(revision).map_err(invalid_data)?;
+        line.push(b'\n');
+        file.set_len(start)?;
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(&line)
+    }
+
+    // Rewrites the history without all but its `keep` newest revisions.
+    pub fn keep_latest_revisions(&self, room_id: &str, file_id: i32, keep: usize) -> io::Result<()> {
+        let revisions = self.load_revisions(room_id, file_id)?;
+        if revisions.len() <= keep {
+            return Ok(());
+        }
+        let mut bytes = Vec::new();
+        for revision in &revisions[revisions.len() - keep..] {
+            bytes.extend(serde_json::to_vec(revision).map_err(invalid_data)?);
+            bytes.push(b'\n');
+        }
+        write_atomic(&self.revisions_path(room_id, file_id), &bytes)
+    }
+
+    // A conversation that doesn't parse is skipped rather than failing the
+    // whole list.
+    pub fn load_conversations(&self, username: &str) -> io::Result<Vec<Conversation>> {
+        let entries = match fs::read_dir(self.conversations_dir(username)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut conversations = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match serde_json::from_slice(&fs::read(&path)?) {
+                Ok(conversation) => conversations.push(conversation),
+                Err(e) => warn!("[storage] Skipping unreadable conversation '{}': {}", path.display(), e),
+            }
+        }
+        Ok(conversations)
+    }
+
+    pub fn save_conversation(&self, username: &str, conversation: &Conversation) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(conversation).map_err(invalid_data)?;
+        write_atomic(&self.conversation_path(username, &conversation.id), &json)
+    }
+
+    pub fn delete_conversation(&self, username: &str, id: &str) -> io::Result<()> {
+        remove_if_exists(&self.conversation_path(username, id))
+    }
+
+    pub fn git_dir(&self, room_id: &str) -> PathBuf {
+        self.root.join("git").join(encode_name(room_id))
+    }
+
+    fn room_dir(&self, room_id: &str) -> PathBuf {
+        self.root.join("rooms").join(encode_name(room_id))
+    }
+
+    fn conversations_dir(&self, username: &str) -> PathBuf {
+        self.root.join("conversations").join(encode_name(username))
+    }
+
+    // Conversation ids are checked to be file-name safe before they get here.
+    fn conversation_path(&self, username: &str, id: &str) -> PathBuf {
+        self.conversations_dir(username).join(format!("{}.json", id))
+    }
+
+    fn file_path(&self, room_id: &str, file_id: i32) -> PathBuf {
+        self.room_dir(room_id).join("files").join(file_id.to_string())
+    }
+
+    fn revisions_path(&self, room_id: &str, file_id: i32) -> PathBuf {
+        self.room_dir(room_id).join("revisions").join(format!("{}.jsonl", file_id))
+    }
+}
+
+// Writes to a temporary sibling and renames it into place, so a crash never
+// leaves a half-written file behind.
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+// Finds the last line of a file read backwards in TAIL_CHUNK pieces. Returns
+// where it starts and its bytes, without the trailing newline.
+fn last_line(file: &mut fs::File) -> io::Result<(u64, Vec<u8>)> {
+    let mut end = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+    loop {
+        let start = end.saturating_sub(TAIL_CHUNK);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        end = start;
+        let text = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        match text.iter().rposition(|&b| b == b'\n') {
+            Some(newline) => return Ok((end + newline as u64 + 1, text[newline + 1..].to_vec())),
+            None if end == 0 => return Ok((0, text.to_vec())),
+            None => {}
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn encode_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn invalid_data(e: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_storage(name: &str) -> Storage {
+        let dir = std::env::temp_dir().join(format!("webcce-storage-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        Storage::new(dir)
+    }
+
+    fn file(id: i32, folder: &str, content: &str, binary: bool) -> File {
+        File { id, name: format!("{}.txt", id), folder: folder.to_string(), content: content.to_string(), version: 3, binary }
+    }
+
+    fn projects() -> Vec<Project> {
+        vec![Project {
+            id: 1,
+            name: "site".to_string(),
+            folders: vec!["css".to_string()],
+            files: vec![file(10, "", "hello", false), file(11, "css", "body {}", false)],
+        }]
+    }
+
+    #[test]
+    fn rooms_round_trip_with_their_contents() {
+        let storage = test_storage("rooms");
+        storage.save_room("team/../1", &projects()).unwrap();
+
+        let rooms = storage.load_rooms().unwrap();
+        let loaded = &rooms["team/../1"];
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].name, "site");
+        assert_eq!(loaded[0].folders, ["css"]);
+        let files: Vec<_> = loaded[0].files.iter().map(|f| (f.id, f.path(), f.content.as_str(), f.version)).collect();
+        assert_eq!(files, [(10, "10.txt".to_string(), "hello", 3), (11, "css/11.txt".to_string(), "body {}", 3)]);
+        assert!(storage.root.join("rooms").join("team%2F%2E%2E%2F1").is_dir());
+    }
+
+    #[test]
+    fn manifests_are_saved_without_contents() {
+        let storage = test_storage("manifest");
+        storage.save_room("room1", &projects()).unwrap();
+        let mut changed = projects();
+        changed[0].name = "renamed".to_string();
+        changed[0].files[0].content = "not written".to_string();
+        storage.save_manifest("room1", &changed).unwrap();
+
+        let rooms = storage.load_rooms().unwrap();
+        assert_eq!(rooms["room1"][0].name, "renamed");
+        assert_eq!(rooms["room1"][0].files[0].content, "hello");
+    }
+
+    #[test]
+    fn binary_contents_are_only_read_on_request() {
+        let storage = test_storage("binary");
+        let mut projects = projects();
+        projects[0].files.push(file(12, "", "", true));
+        storage.save_bytes("room1", 12, &[0, 159, 146, 150]).unwrap();
+        storage.save_room("room1", &projects).unwrap();
+
+        let rooms = storage.load_rooms().unwrap();
+        assert!(rooms["room1"][0].files[2].binary);
+        assert_eq!(rooms["room1"][0].files[2].content, "");
+        assert_eq!(storage.read_file("room1", 12).unwrap(), [0, 159, 146, 150]);
+    }
+
+    #[test]
+    fn deleted_files_lose_their_contents() {
+        let storage = test_storage("delete");
+        storage.save_room("room1", &projects()).unwrap();
+        storage.delete_file("room1", 10).unwrap();
+        storage.delete_file("room1", 10).unwrap();
+        assert!(storage.read_file("room1", 10).is_err());
+    }
+
+    fn revision(id: u32, content: &str) -> Revision {
+        Revision { id, author: "alice".to_string(), timestamp: 0, content: content.to_string(), autosave: false }
+    }
+
+    fn history(storage: &Storage) -> Vec<(u32, String)> {
+        storage.load_revisions("room1", 10).unwrap().into_iter().map(|r| (r.id, r.content)).collect()
+    }
+
+    #[test]
+    fn revisions_round_trip_oldest_first() {
+        let storage = test_storage("revisions");
+        assert!(storage.last_revision("room1", 10).unwrap().is_none());
+        for id in 1..=3 {
+            storage.append_revision("room1", 10, &revision(id, &format!("v{}\nline", id))).unwrap();
+        }
+        assert_eq!(history(&storage), [(1, "v1\nline".to_string()), (2, "v2\nline".to_string()), (3, "v3\nline".to_string())]);
+        assert_eq!(storage.last_revision("room1", 10).unwrap().unwrap().id, 3);
+
+        storage.keep_latest_revisions("room1", 10, 2).unwrap();
+        assert_eq!(history(&storage).iter().map(|(id, _)| *id).collect::<Vec<_>>(), [2, 3]);
+    }
+
+    #[test]
+    fn the_last_revision_can_be_replaced() {
+        let storage = test_storage("replace");
+        storage.append_revision("room1", 10, &revision(1, "one")).unwrap();
+        storage.append_revision("room1", 10, &revision(2, &"x".repeat(TAIL_CHUNK as usize))).unwrap();
+        storage.replace_last_revision("room1", 10, &revision(2, "two")).unwrap();
+        storage.replace_last_revision("room1", 10, &revision(2, "second")).unwrap();
+        assert_eq!(history(&storage), [(1, "one".to_string()), (2, "second".to_string())]);
+        assert_eq!(storage.last_revision("room1", 10).unwrap().unwrap().content, "second");
+    }
+
+    #[test]
+    fn a_truncated_last_revision_is_skipped() {
+        let storage = test_storage("truncated");
+        storage.append_revision("room1", 10, &revision(1, "one")).unwrap();
+        let path = storage.revisions_path("room1", 10);
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"id\":2,\"auth").unwrap();
+        assert_eq!(storage.last_revision("room1", 10).unwrap().unwrap().id, 1);
+        assert_eq!(history(&storage), [(1, "one".to_string())]);
+    }
+}
@@ -0,0 +1,315 @@
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
+use std::fmt;
+use std::fs::{read_to_string, rename, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+const USERS_FILE: &str = "users.txt";
+const USERS_DB: &str = "users.db";
+
+// A user record as persisted by a store. Both hashes are argon2 PHC strings.
+#[derive(Clone, Debug)]
+pub struct StoredUser {
+    pub username: String,
+    pub password_hash: String,
+    pub room_id_hash: String,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Duplicate,
+    InvalidUsername,
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Duplicate => write!(f, "username already exists"),
+            StoreError::InvalidUsername => write!(f, "username cannot be stored"),
+            StoreError::Backend(e) => write!(f, "user store error: {}", e),
+        }
+    }
+}
+
+// Where users live. `insert` must fail with `StoreError::Duplicate` when the
+// username is taken, so callers don't need their own check-then-write lock.
+pub trait UserStore: Send + Sync {
+    fn find(&self, username: &str) -> Result<Option<StoredUser>, StoreError>;
+    fn insert(&self, user: &StoredUser) -> Result<(), StoreError>;
+}
+
+// Picks the backend from USER_STORE ("sqlite" by default, or "file").
+// The SQLite store imports an existing users file (USERS_FILE, users.txt by
+// default) the first time it starts.
+pub fn open_from_env() -> Arc<dyn UserStore> {
+    let users_file = std::env::var("USERS_FILE").unwrap_or_else(|_| USERS_FILE.to_string());
+    match std::env::var("USER_STORE").as_deref() {
+        Ok("file") => {
+            info!("[users] Using file user store at '{}'.", users_file);
+            Arc::new(FileUserStore::new(users_file))
+        }
+        _ => {
+            let path = std::env::var("USERS_DB").unwrap_or_else(|_| USERS_DB.to_string());
+            info!("[users] Using SQLite user store at '{}'.", path);
+            let store = SqliteUserStore::open(&path).expect("failed to open SQLite user store");
+            if let Err(e) = store.migrate_from_file(&users_file) {
+                warn!("[users] Could not import '{}': {}", users_file, e);
+            }
+            Arc::new(store)
+        }
+    }
+}
+
+// --- users.txt ---
+
+// The original `username,<password hash>,<room id hash>` line format.
+pub struct FileUserStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileUserStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileUserStore { path: path.into(), lock: Mutex::new(()) }
+    }
+
+    fn read_all(&self) -> Result<Vec<StoredUser>, StoreError> {
+        Ok(self.read_checked()?.0)
+    }
+
+    // Like read_all, but also returns the line numbers of non-blank lines that
+    // could not be parsed.
+    fn read_checked(&self) -> Result<(Vec<StoredUser>, Vec<usize>), StoreError> {
+        let contents = match read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), Vec::new())),
+            Err(e) => return Err(StoreError::Backend(e.to_string())),
+        };
+        let mut users = Vec::new();
+        let mut unreadable = Vec::new();
+        for (index, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match parse_user_line(line) {
+                Some(user) => users.push(user),
+                None => unreadable.push(index + 1),
+            }
+        }
+        Ok((users, unreadable))
+    }
+}
+
+impl UserStore for FileUserStore {
+    fn find(&self, username: &str) -> Result<Option<StoredUser>, StoreError> {
+        let _lock = self.lock.lock().unwrap();
+        Ok(self.read_all()?.into_iter().find(|u| u.username == username))
+    }
+
+    fn insert(&self, user: &StoredUser) -> Result<(), StoreError> {
+        if user.username.contains(',') || user.username.contains('\n') {
+            return Err(StoreError::InvalidUsername);
+        }
+        let _lock = self.lock.lock().unwrap();
+        if self.read_all()?.iter().any(|u| u.username == user.username) {
+            return Err(StoreError::Duplicate);
+        }
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        writeln!(file, "{},{},{}", user.username, user.password_hash, user.room_id_hash)
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+}
+
+fn parse_user_line(line: &str) -> Option<StoredUser> {
+    let (username, rest) = line.split_once(',')?;
+    let separator = ",$argon2id$";
+
+    let separator_index = rest.find(separator)?;
+    Some(StoredUser {
+        username: username.to_string(),
+        password_hash: rest[..separator_index].to_string(),
+        room_id_hash: rest[separator_index + 1..].to_string(),
+    })
+}
+
+// --- SQLite ---
+
+pub struct SqliteUserStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteUserStore {
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS users (
+                username      TEXT PRIMARY KEY NOT NULL,
+                password_hash TEXT NOT NULL,
+                room_id_hash  TEXT NOT NULL,
+                created_at    INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            );",
+        )?;
+        Ok(SqliteUserStore { conn: Mutex::new(conn) })
+    }
+
+    // Imports every user from a legacy users.txt and renames the file to
+    // `<name>.migrated` so the import only ever runs once. Usernames that
+    // already exist in the database are skipped. If any line can't be read,
+    // the rest are imported but the file is left in place and an error is
+    // returned, so no account disappears unnoticed; the import runs again on
+    // the next start once the file is fixed.
+    pub fn migrate_from_file(&self, path: impl AsRef<Path>) -> Result<usize, StoreError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(0);
+        }
+        let (users, unreadable) = FileUserStore::new(path).read_checked()?;
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(backend)?;
+        let mut imported = 0;
+        for user in &users {
+            imported += tx
+                .execute(
+                    "INSERT OR IGNORE INTO users (username, password_hash, room_id_hash) VALUES (?1, ?2, ?3)",
+                    params![user.username, user.password_hash, user.room_id_hash],
+                )
+                .map_err(backend)?;
+        }
+        tx.commit().map_err(backend)?;
+
+        if !unreadable.is_empty() {
+            for line in &unreadable {
+                warn!("[users] Could not read line {} of '{}'.", line, path.display());
+            }
+            return Err(StoreError::Backend(format!(
+                "imported {} users but {} lines of '{}' could not be read; the file was kept",
+                imported,
+                unreadable.len(),
+                path.display()
+            )));
+        }
+
+        let mut migrated = path.as_os_str().to_owned();
+        migrated.push(".migrated");
+        rename(path, &migrated).map_err(|e| StoreError::Backend(e.to_string()))?;
+        info!("[users] Imported {} of {} users from '{}'.", imported, users.len(), path.display());
+        Ok(imported)
+    }
+}
+
+impl UserStore for SqliteUserStore {
+    fn find(&self, username: &str) -> Result<Option<StoredUser>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT username, password_hash, room_id_hash FROM users WHERE username = ?1",
+            params![username],
+            |row| {
+                Ok(StoredUser {
+                    username: row.get(0)?,
+                    password_hash: row.get(1)?,
+                    room_id_hash: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(backend)
+    }
+
+    fn insert(&self, user: &StoredUser) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        match conn.execute(
+            "INSERT INTO users (username, password_hash, room_id_hash) VALUES (?1, ?2, ?3)",
+            params![user.username, user.password_hash, user.room_id_hash],
+        ) {
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => {
+                Err(StoreError::Duplicate)
+            }
+            Err(e) => Err(backend(e)),
+        }
+    }
+}
+
+fn backend(e: rusqlite::Error) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("webcce-users-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn user(username: &str) -> StoredUser {
+        StoredUser {
+            username: username.to_string(),
+            password_hash: format!("$argon2id$v=19$m=19456,t=2,p=1$salt${}", username),
+            room_id_hash: "$argon2id$v=19$m=19456,t=2,p=1$salt$room".to_string(),
+        }
+    }
+
+    fn assert_signup(store: &dyn UserStore) {
+        store.insert(&user("alice")).unwrap();
+        assert!(matches!(store.insert(&user("alice")), Err(StoreError::Duplicate)));
+        let found = store.find("alice").unwrap().unwrap();
+        assert_eq!(found.password_hash, user("alice").password_hash);
+        assert_eq!(found.room_id_hash, user("alice").room_id_hash);
+        assert!(store.find("bob").unwrap().is_none());
+    }
+
+    #[test]
+    fn file_store_rejects_duplicate_signups() {
+        assert_signup(&FileUserStore::new(temp_dir("file-duplicate").join("users.txt")));
+    }
+
+    #[test]
+    fn file_store_rejects_usernames_it_cannot_store() {
+        let store = FileUserStore::new(temp_dir("file-invalid").join("users.txt"));
+        assert!(matches!(store.insert(&user("a,b")), Err(StoreError::InvalidUsername)));
+        assert!(matches!(store.insert(&user("a\nb")), Err(StoreError::InvalidUsername)));
+    }
+
+    #[test]
+    fn sqlite_store_rejects_duplicate_signups() {
+        assert_signup(&SqliteUserStore::open(":memory:").unwrap());
+    }
+
+    #[test]
+    fn migration_imports_users_and_renames_the_file() {
+        let dir = temp_dir("migrate");
+        let path = dir.join("users.txt");
+        let file = FileUserStore::new(&path);
+        file.insert(&user("alice")).unwrap();
+        file.insert(&user("bob")).unwrap();
+        let store = SqliteUserStore::open(":memory:").unwrap();
+        store.insert(&user("bob")).unwrap();
+
+        assert_eq!(store.migrate_from_file(&path).unwrap(), 1);
+        assert!(store.find("alice").unwrap().is_some());
+        assert!(!path.exists());
+        assert!(dir.join("users.txt.migrated").exists());
+        assert_eq!(store.migrate_from_file(&path).unwrap(), 0);
+    }
+
+    #[test]
+    fn migration_keeps_a_file_with_unreadable_lines() {
+        let path = temp_dir("migrate-unreadable").join("users.txt");
+        FileUserStore::new(&path).insert(&user("alice")).unwrap();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"broken line\n").unwrap();
+        let store = SqliteUserStore::open(":memory:").unwrap();
+
+        assert!(matches!(store.migrate_from_file(&path), Err(StoreError::Backend(_))));
+        assert!(store.find("alice").unwrap().is_some());
+        assert!(path.exists());
+    }
+}
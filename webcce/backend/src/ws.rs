@@ -1,39 +1,161 @@
+use crate::autosave;
+use crate::events;
+use crate::files::{authorize_file, find_file};
+use crate::protocol::{ClientMessage, ErrorCode, PresenceUser, ProtocolError, Selection, ServerMessage, PROTOCOL_VERSION};
+use crate::session::AuthUser;
 use crate::state::{AppState, Room, UserState};
 use axum::{
     extract::{ ws::{Message, WebSocket}, Path, State, WebSocketUpgrade },
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 use futures::{stream::StreamExt, SinkExt};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 use tokio::sync::mpsc;
-use tracing::info; // NEW!
+use tracing::{info, warn};
+
+pub fn send_message(sender: &mpsc::UnboundedSender<Message>, message: &ServerMessage) {
+    match serde_json::to_string(message) {
+        Ok(text) => {
+            let _ = sender.send(Message::Text(text));
+        }
+        Err(e) => warn!("[ws] Could not serialize message: {}", e),
+    }
+}
+
+// Colors for remote cursors. A user gets the same color in every file.
+const CURSOR_COLORS: [&str; 8] = ["#e06c75", "#98c379", "#e5c07b", "#61afef", "#c678dd", "#56b6c2", "#d19a66", "#be5046"];
+
+fn color_for(username: &str) -> &'static str {
+    let hash = username.bytes().fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+    CURSOR_COLORS[hash as usize % CURSOR_COLORS.len()]
+}
+
+// Identifies one socket; a user with several tabs open has several.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+// Everyone in the room except `username`, for the roster sent on connect.
+// A user with several connections is listed once.
+fn roster<'a>(room: &'a Room, username: &str) -> Vec<PresenceUser<'a>> {
+    let mut users: BTreeMap<&str, PresenceUser> = BTreeMap::new();
+    for user in room.connections.values().filter(|user| user.username != username) {
+        let entry = users
+            .entry(&user.username)
+            .or_insert(PresenceUser { username: &user.username, color: user.color, selection: None });
+        entry.selection = entry.selection.or(user.selection);
+    }
+    users.into_values().collect()
+}
+
+fn is_present(room: &Room, username: &str) -> bool {
+    room.connections.values().any(|user| user.username == username)
+}
+
+// Sends `message` to every connection in the room except `connection_id`.
+// Closes the rooms of files that were just deleted: everyone in them is told
+// why and disconnected, and nothing is written back. The caller holds the
+// file system lock, which comes before the room manager.
+pub async fn close_deleted_rooms(state: &AppState, file_ids: impl IntoIterator<Item = i32>) {
+    let mut room_manager = state.room_manager.lock().await;
+    for file_id in file_ids {
+        let Some(room) = room_manager.remove(&file_id) else { continue };
+        let error = ProtocolError::new(ErrorCode::FileDeleted, "The file was deleted");
+        for user in room.connections.values() {
+            send_message(&user.sender, &error.to_message());
+            let _ = user.sender.send(Message::Close(None));
+        }
+        info!("[ws] Closed the room for deleted file {}. Connections closed: {}", file_id, room.connections.len());
+    }
+}
+
+fn broadcast(room: &Room, connection_id: u64, message: &ServerMessage) {
+    for (id, user) in &room.connections {
+        if *id != connection_id {
+            send_message(&user.sender, message);
+        }
+    }
+}
+
+// Sends a presence `message` about `username` to everyone else, skipping the
+// user's own other tabs.
+fn broadcast_presence(room: &Room, username: &str, message: &ServerMessage) {
+    for user in room.connections.values().filter(|user| user.username != username) {
+        send_message(&user.sender, message);
+    }
+}
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
-    Path((file_id, username)): Path<(i32, String)>,
-) -> impl IntoResponse {
-    info!("[ws] ==> New WebSocket connection request for file_id: {} from user: '{}'", file_id, username);
-    ws.on_upgrade(move |socket| handle_socket(socket, state, file_id, username))
+    user: AuthUser,
+    Path(file_id): Path<i32>,
+) -> Response {
+    info!("[ws] ==> New WebSocket connection request for file_id: {} from user: '{}'", file_id, user.username);
+    {
+        let file_system = state.file_system.lock().await;
+        if let Err(status) = authorize_file(&file_system, &user, file_id) {
+            info!("[ws] <== Rejected connection to file {} for user '{}' ({}).", file_id, user.username, status);
+            return status.into_response();
+        }
+        if find_file(&file_system, &user.room_id, file_id).is_some_and(|f| f.binary) {
+            info!("[ws] <== Rejected connection to binary file {}.", file_id);
+            return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Binary files cannot be edited").into_response();
+        }
+    }
+    ws.on_upgrade(move |socket| handle_socket(socket, state, file_id, user))
+        .into_response()
 }
-async fn handle_socket(socket: WebSocket, state: AppState, file_id: i32, username: String) {
+async fn handle_socket(socket: WebSocket, state: AppState, file_id: i32, user: AuthUser) {
+    let AuthUser { username, room_id } = user;
+    let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
     let (mut socket_sender, mut socket_receiver) = socket.split();
     let (user_sender, mut user_receiver) = mpsc::unbounded_channel::<Message>();
     tokio::spawn(async move { while let Some(message) = user_receiver.recv().await { if socket_sender.send(message).await.is_err() { break; } } });
     {
+        // Both locks are held so the room can't be closed and written back
+        // between reading the saved content and joining.
+        let file_system = state.file_system.lock().await;
+        // Deleted since the upgrade was authorized.
+        let Some(file) = find_file(&file_system, &room_id, file_id) else {
+            send_message(&user_sender, &ProtocolError::new(ErrorCode::FileDeleted, "The file was deleted").to_message());
+            let _ = user_sender.send(Message::Close(None));
+            return;
+        };
         let mut room_manager = state.room_manager.lock().await;
-        let room = room_manager.entry(file_id).or_insert_with(|| Room { users: HashMap::new() });
-        room.users.insert(username.clone(), UserState { username: username.clone(), sender: user_sender });
-        info!("[ws] User '{}' joined room for file {}. Total users: {}", username, file_id, room.users.len());
+        let room = room_manager.entry(file_id).or_insert_with(|| Room::new(&room_id, file.content.clone(), file.version));
+        let color = color_for(&username);
+        let already_present = is_present(room, &username);
+        room.connections.insert(connection_id, UserState { username: username.clone(), sender: user_sender.clone(), color, selection: None, revision: room.document.revision() });
+        send_message(&user_sender, &ServerMessage::Snapshot { version: PROTOCOL_VERSION, revision: room.document.revision(), content: &room.document.content });
+        send_message(&user_sender, &ServerMessage::Presence { users: roster(room, &username) });
+        if !already_present {
+            broadcast_presence(room, &username, &ServerMessage::Join { username: &username, color });
+        }
+        info!("[ws] User '{}' joined room for file {} at revision {} (connection {}). Total connections: {}", username, file_id, room.document.revision(), connection_id, room.connections.len());
     }
     while let Some(Ok(msg)) = socket_receiver.next().await {
-        if let Message::Text(text) = msg {
-            let room_manager = state.room_manager.lock().await;
-            if let Some(room) = room_manager.get(&file_id) {
-                for (other_username, other_user) in &room.users {
-                    if &username != other_username {
-                        let _ = other_user.sender.send(Message::Text(text.clone()));
-                    }
+        let Message::Text(text) = msg else { continue };
+        let message = match ClientMessage::parse(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                send_message(&user_sender, &e.to_message());
+                continue;
+            }
+        };
+        match handle_message(&state, file_id, &room_id, connection_id, &username, &user_sender, message).await {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Close) => {
+                let _ = user_sender.send(Message::Close(None));
+                break;
+            }
+            Err(e) => {
+                info!("[ws] Rejected message from '{}' on file {}: {}", username, file_id, e.message);
+                send_message(&user_sender, &e.to_message());
+                if e.code == ErrorCode::UnsupportedVersion {
+                    let _ = user_sender.send(Message::Close(None));
+                    break;
                 }
             }
         }
@@ -41,8 +163,91 @@ async fn handle_socket(socket: WebSocket, state: AppState, file_id: i32, usernam
     {
         let mut room_manager = state.room_manager.lock().await;
         if let Some(room) = room_manager.get_mut(&file_id) {
-            room.users.remove(&username);
-            info!("[ws] <== User '{}' disconnected from file {}. Users remaining: {}", username, file_id, room.users.len());
+            room.connections.remove(&connection_id);
+            room.trim_history();
+            if !is_present(room, &username) {
+                broadcast_presence(room, &username, &ServerMessage::Leave { username: &username });
+            }
+            info!("[ws] <== User '{}' disconnected from file {} (connection {}). Connections remaining: {}", username, file_id, connection_id, room.connections.len());
+        }
+    }
+    autosave::close_room(&state, file_id).await;
+}
+
+enum Flow {
+    Continue,
+    Close,
+}
+
+async fn handle_message(
+    state: &AppState,
+    file_id: i32,
+    room_id: &str,
+    connection_id: u64,
+    username: &str,
+    sender: &mpsc::UnboundedSender<Message>,
+    message: ClientMessage,
+) -> Result<Flow, ProtocolError> {
+    match message {
+        ClientMessage::Ping { nonce, revision } => {
+            // A client that only watches never sends edits, so its pings are
+            // what let the room forget operations it will never base one on.
+            if let Some(revision) = revision {
+                let mut room_manager = state.room_manager.lock().await;
+                let Some(room) = room_manager.get_mut(&file_id) else { return Ok(Flow::Close) };
+                if revision > room.document.revision() {
+                    return Err(ProtocolError::new(
+                        ErrorCode::OutOfRange,
+                        format!("Revision {} is newer than the document (revision {})", revision, room.document.revision()),
+                    ));
+                }
+                room.acknowledge(connection_id, revision);
+                room.trim_history();
+            }
+            send_message(sender, &ServerMessage::Pong { nonce });
+        }
+        ClientMessage::Leave => return Ok(Flow::Close),
+        ClientMessage::Save => autosave::flush(state, file_id, Some(username)).await.map_err(|status| {
+            warn!("[ws] Could not save file {} for '{}': {}", file_id, username, status);
+            ProtocolError::new(ErrorCode::SaveFailed, "Could not save the file")
+        })?,
+        message => {
+            let mut room_manager = state.room_manager.lock().await;
+            let Some(room) = room_manager.get_mut(&file_id) else { return Ok(Flow::Close) };
+            message.validate(&room.document.content)?;
+            match message {
+                ClientMessage::Edit { revision, operation } => {
+                    let operation = room
+                        .apply_edit(revision, operation, username, Instant::now())
+                        .map_err(|e| ProtocolError::new(ErrorCode::InvalidOperation, e.to_string()))?;
+                    room.acknowledge(connection_id, revision);
+                    room.trim_history();
+                    let revision = room.document.revision();
+                    send_message(sender, &ServerMessage::Ack { revision });
+                    broadcast(room, connection_id, &ServerMessage::Edit { revision, operation: &operation, username });
+                    // Lock order: the room manager must be released before
+                    // the file system is locked to find the file's project.
+                    drop(room_manager);
+                    events::publish_live_edit(state, room_id, file_id).await;
+                }
+                ClientMessage::Cursor { position } => {
+                    set_selection(room, connection_id, Selection { anchor: position, head: position });
+                    broadcast_presence(room, username, &ServerMessage::Cursor { username, position });
+                }
+                ClientMessage::Selection { anchor, head } => {
+                    set_selection(room, connection_id, Selection { anchor, head });
+                    broadcast_presence(room, username, &ServerMessage::Selection { username, anchor, head });
+                }
+                // Join only announces the protocol version, which validate() checked.
+                _ => {}
+            }
         }
     }
-}
\ No newline at end of file
+    Ok(Flow::Continue)
+}
+
+fn set_selection(room: &mut Room, connection_id: u64, selection: Selection) {
+    if let Some(user) = room.connections.get_mut(&connection_id) {
+        user.selection = Some(selection);
+    }
+}
@@ -1,69 +1,112 @@
-const LS_KEY = 'chat_ui_demo_v1';
+const API_BASE_URL = 'https://api.mp2upnhs.my';
+const SESSION_TOKEN = sessionStorage.getItem('webcce_token') || localStorage.getItem('webcce_token');
+// Conversations live on the server; only the open one is remembered here.
+const ACTIVE_KEY = 'chat_active_conversation';
 const historyEl = document.getElementById('history');
 const messagesEl = document.getElementById('messages');
 const form = document.getElementById('form');
 const input = document.getElementById('input');
 const convTitle = document.getElementById('convTitle');
 const newConvBtn = document.getElementById('newConv');
-const clearBtn = document.getElementById('clearAll');
+const deleteBtn = document.getElementById('deleteConv');
 
-let store = loadStore();
-let activeId = store.activeId || null;
+// Summaries from the server, most recent first
+let conversations = [];
+// The open conversation. A new one only exists locally until its first reply.
+let active = null;
 
-function loadStore() {
-  try {
-    const raw = localStorage.getItem(LS_KEY);
-    return raw ? JSON.parse(raw) : { conversations: [], activeId: null };
-  } catch {
-    return { conversations: [], activeId: null };
+async function apiFetch(path, options = {}) {
+  const headers = { ...(options.headers || {}), 'Authorization': `Bearer ${SESSION_TOKEN}` };
+  const resp = await fetch(`${API_BASE_URL}${path}`, { ...options, headers });
+  if (resp.status === 401) {
+    sessionStorage.removeItem('webcce_token');
+    localStorage.removeItem('webcce_token');
+    window.location.href = './login.html';
   }
+  return resp;
+}
+
+function formatTime(seconds) {
+  return seconds ? new Date(seconds * 1000).toLocaleString() : '';
 }
-function saveStore() {
-  localStorage.setItem(LS_KEY, JSON.stringify(store));
+
+async function loadConversations() {
+  const resp = await apiFetch('/api/conversations');
+  if (resp.ok) conversations = await resp.json();
+  renderHistory();
 }
 
 function renderHistory() {
   historyEl.innerHTML = '';
-  store.conversations.forEach(c => {
+  conversations.forEach(c => {
     const b = document.createElement('button');
-    b.textContent = c.title || c.id.slice(0, 6);
+    b.textContent = c.title;
+    b.title = formatTime(c.updated_at);
     b.onclick = () => setActive(c.id);
-    if (c.id === activeId) b.classList.add('active');
+    if (active && c.id === active.id) b.classList.add('active');
     historyEl.appendChild(b);
   });
 }
 
-function setActive(id) {
-  activeId = id;
-  store.activeId = id;
-  saveStore();
+async function setActive(id) {
+  const resp = await apiFetch(`/api/conversation/${encodeURIComponent(id)}`);
+  if (!resp.ok) {
+    createConv();
+    return;
+  }
+  active = await resp.json();
+  active.messages.forEach(m => { m.meta = formatTime(m.timestamp); });
+  localStorage.setItem(ACTIVE_KEY, id);
   renderHistory();
   renderMessages();
 }
 
 function createConv() {
-  const id = 'c_' + Date.now().toString(36);
-  const conv = { id, title: 'Conversation ' + (store.conversations.length + 1), messages: [] };
-  store.conversations.unshift(conv);
-  setActive(id);
-  saveStore();
+  const id = 'c_' + Date.now().toString(36) + Math.random().toString(36).slice(2, 6);
+  active = { id, title: 'New conversation', messages: [], draft: true };
+  localStorage.removeItem(ACTIVE_KEY);
+  renderHistory();
+  renderMessages();
 }
 
-function clearAll() {
-  if (!confirm('Clear all conversations?')) return;
-  store = { conversations: [], activeId: null };
-  saveStore();
-  activeId = null;
-  renderHistory();
+async function renameConv() {
+  if (!active || active.draft) return;
+  const title = prompt('Rename conversation', active.title);
+  if (!title || !title.trim()) return;
+  const resp = await apiFetch('/api/conversation/rename', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ id: active.id, title })
+  });
+  if (!resp.ok) {
+    alert(await resp.text());
+    return;
+  }
+  active.title = (await resp.json()).title;
   renderMessages();
+  await loadConversations();
+}
+
+async function deleteConv() {
+  if (!active) return;
+  if (!active.draft) {
+    if (!confirm(`Delete "${active.title}"?`)) return;
+    const resp = await apiFetch(`/api/conversation/${encodeURIComponent(active.id)}`, { method: 'DELETE' });
+    if (!resp.ok && resp.status !== 404) {
+      alert(await resp.text());
+      return;
+    }
+  }
+  await loadConversations();
+  if (conversations.length) await setActive(conversations[0].id);
+  else createConv();
 }
 
 function renderMessages() {
   messagesEl.innerHTML = '';
-  const conv = store.conversations.find(c => c.id === activeId);
-  convTitle.textContent = conv ? conv.title : 'New conversation';
-  if (!conv) return;
-  conv.messages.forEach(m => {
+  convTitle.textContent = active ? active.title : 'New conversation';
+  if (!active) return;
+  active.messages.forEach(m => {
     const div = document.createElement('div');
     div.className = 'msg' + (m.role === 'user' ? ' me' : '');
     if (m.meta) {
@@ -82,75 +125,100 @@ function renderMessages() {
 }
 
 async function sendMessage(text) {
-  if (!activeId) createConv();
-  const conv = store.conversations.find(c => c.id === store.activeId);
-  const now = new Date().toLocaleString();
+  if (!active) createConv();
+  const conv = active;
 
-  const userMsg = { role: 'user', content: text, meta: now };
-  conv.messages.push(userMsg);
-  saveStore();
+  // Only the new message is sent; the server has the rest of the conversation.
+  conv.messages.push({ role: 'user', content: text, meta: new Date().toLocaleString() });
   renderMessages();
 
-  // typing indicator
+  // typing indicator, replaced by the reply as it streams in
   const typingMsg = { role: 'system', content: 'Assistant is typing...', meta: '...' };
   conv.messages.push(typingMsg);
   renderMessages();
 
+  const replyMsg = { role: 'assistant', content: '', meta: new Date().toLocaleString() };
+  let saved = false;
+
   try {
-    const resp = await fetch('/api/chat', {
+    const resp = await apiFetch('/chat/stream', {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({
-        conversationId: conv.id,
-        messages: conv.messages
-          .filter(m => m.role !== 'system')
-          .map(m => ({ role: m.role, content: m.content }))
-      })
+      body: JSON.stringify({ conversation_id: conv.id, message: text })
     });
 
     // remove typing
-    conv.messages = conv.messages.filter(m => m.role !== 'system');
+    conv.messages = conv.messages.filter(m => m !== typingMsg);
     if (!resp.ok) {
-      // try to get text body for debugging and show to user
-      const txt = await resp.text().catch(() => `HTTP ${resp.status}`);
-      conv.messages.push({ role: 'assistant', content: `(openai error ${resp.status}): ${txt}`, meta: new Date().toLocaleString() });
-      saveStore();
+      const txt = await resp.text().catch(() => '');
+      conv.messages.push({ role: 'assistant', content: `(error ${resp.status}) ${txt}`, meta: new Date().toLocaleString() });
       renderMessages();
       return;
     }
 
-    // ensure response is JSON and has reply
-    const ct = resp.headers.get('content-type') || '';
-    if (!ct.includes('application/json')) {
-      const txt = await resp.text().catch(() => '(no body)');
-      conv.messages.push({ role: 'assistant', content: `(invalid content-type) ${txt}`, meta: new Date().toLocaleString() });
-      saveStore();
-      renderMessages();
-      return;
-    }
-
-    const data = await resp.json().catch(e => ({ error: String(e) }));
-    if (data && typeof data.reply === 'string') {
-      conv.messages.push({ role: 'assistant', content: data.reply, meta: new Date().toLocaleString() });
-    } else if (data && data.error) {
-      conv.messages.push({ role: 'assistant', content: `(error parsing json) ${data.error}`, meta: new Date().toLocaleString() });
-    } else {
-      conv.messages.push({ role: 'assistant', content: '(no reply field in response)', meta: new Date().toLocaleString() });
-    }
+    conv.messages.push(replyMsg);
+    await readChatStream(resp, (event, data) => {
+      if (event === 'delta') {
+        replyMsg.content += data.content;
+      } else if (event === 'done') {
+        replyMsg.content = data.reply;
+        saved = true;
+      } else if (event === 'error') {
+        replyMsg.content += (replyMsg.content ? '\n\n' : '') + `(error) ${data.message}`;
+      }
+      if (conv === active) renderMessages();
+    });
+    if (!replyMsg.content) replyMsg.content = '(no reply)';
 
   } catch (err) {
     // network or other unexpected error
-    conv.messages = conv.messages.filter(m => m.role !== 'system');
+    conv.messages = conv.messages.filter(m => m !== typingMsg && m !== replyMsg);
     conv.messages.push({
       role: 'assistant',
-      content: `(network fallback) ${String(err)}`,
+      content: `(network error) ${String(err)}`,
       meta: new Date().toLocaleString()
     });
     console.error('sendMessage error', err);
   }
 
-  saveStore();
-  renderMessages();
+  // A new conversation now exists on the server, titled after its first message.
+  if (saved) {
+    await loadConversations();
+    const summary = conversations.find(c => c.id === conv.id);
+    if (summary) conv.title = summary.title;
+    if (conv.draft) {
+      conv.draft = false;
+      if (conv === active) localStorage.setItem(ACTIVE_KEY, conv.id);
+    }
+  }
+  if (conv === active) renderMessages();
+}
+
+// EventSource can only GET, so the server-sent events from the POST are
+// parsed here. Calls onEvent(name, data) for each event until the stream ends.
+async function readChatStream(resp, onEvent) {
+  const reader = resp.body.getReader();
+  const decoder = new TextDecoder();
+  let buffer = '';
+  for (;;) {
+    const { value, done } = await reader.read();
+    if (done) break;
+    buffer += decoder.decode(value, { stream: true });
+    let end;
+    while ((end = buffer.indexOf('\n\n')) !== -1) {
+      const block = buffer.slice(0, end);
+      buffer = buffer.slice(end + 2);
+      let event = 'message';
+      const data = [];
+      block.split('\n').forEach(line => {
+        if (line.startsWith('event:')) event = line.slice(6).trim();
+        else if (line.startsWith('data:')) data.push(line.slice(5).replace(/^ /, ''));
+      });
+      // keep-alive comments carry no data
+      if (!data.length) continue;
+      onEvent(event, JSON.parse(data.join('\n')));
+    }
+  }
 }
 
 // --- form and keyboard handling ---
@@ -194,12 +262,18 @@ form.querySelector('.send').addEventListener('click', e => {
 });
 
 newConvBtn.addEventListener('click', () => createConv());
-clearBtn.addEventListener('click', () => clearAll());
+deleteBtn.addEventListener('click', () => deleteConv());
+convTitle.addEventListener('click', () => renameConv());
 
 // initialize
-if (!store.conversations.length) createConv();
-else if (store.activeId) setActive(store.activeId);
-else setActive(store.conversations[0].id);
-
-renderHistory();
-renderMessages();
\ No newline at end of file
+(async () => {
+  if (!SESSION_TOKEN) {
+    window.location.href = './login.html';
+    return;
+  }
+  await loadConversations();
+  const lastId = localStorage.getItem(ACTIVE_KEY);
+  if (lastId && conversations.some(c => c.id === lastId)) await setActive(lastId);
+  else if (conversations.length) await setActive(conversations[0].id);
+  else createConv();
+})();
\ No newline at end of file
@@ -22,10 +22,16 @@ document.getElementById("login_form").addEventListener("submit", async (e) => {
             headers: {
                 "Content-Type": "application/json"
             },
+            credentials: "include",
             body: JSON.stringify({ username, password, room_id })
         });
         
         if (response.ok) {
+            const session = await response.json();
+            const storage = document.getElementById("remember").checked ? localStorage : sessionStorage;
+            storage.setItem("webcce_token", session.token);
+            storage.setItem("webcce_room", session.room_id);
+            storage.setItem("webcce_username", session.username);
             window.location.href = "./index.html";
         } else {
             const errorMessage = await response.json();
@@ -0,0 +1,250 @@
+// Minimal operational transformation client, wire-compatible with the
+// backend's ot.rs: an operation is an array where a positive number retains,
+// a negative number deletes and a string inserts. Offsets are JavaScript
+// string indices (UTF-16 code units), the same units Monaco uses.
+(function (global) {
+    class TextOperation {
+        constructor() {
+            this.ops = [];
+            this.baseLength = 0;
+            this.targetLength = 0;
+        }
+
+        static isRetain(op) { return typeof op === 'number' && op > 0; }
+        static isInsert(op) { return typeof op === 'string'; }
+        static isDelete(op) { return typeof op === 'number' && op < 0; }
+
+        retain(n) {
+            if (n === 0) return this;
+            this.baseLength += n;
+            this.targetLength += n;
+            const last = this.ops.length - 1;
+            if (TextOperation.isRetain(this.ops[last])) this.ops[last] += n;
+            else this.ops.push(n);
+            return this;
+        }
+
+        insert(str) {
+            if (str === '') return this;
+            this.targetLength += str.length;
+            const ops = this.ops;
+            const last = ops.length - 1;
+            if (TextOperation.isInsert(ops[last])) {
+                ops[last] += str;
+            } else if (TextOperation.isDelete(ops[last])) {
+                // Keep inserts in front of deletes, like the server does.
+                if (TextOperation.isInsert(ops[last - 1])) ops[last - 1] += str;
+                else { ops[last + 1] = ops[last]; ops[last] = str; }
+            } else {
+                ops.push(str);
+            }
+            return this;
+        }
+
+        delete(n) {
+            if (n === 0) return this;
+            if (n > 0) n = -n;
+            this.baseLength -= n;
+            const last = this.ops.length - 1;
+            if (TextOperation.isDelete(this.ops[last])) this.ops[last] += n;
+            else this.ops.push(n);
+            return this;
+        }
+
+        isNoop() {
+            return this.ops.length === 0 || (this.ops.length === 1 && TextOperation.isRetain(this.ops[0]));
+        }
+
+        toJSON() { return this.ops; }
+
+        static fromJSON(ops) {
+            const operation = new TextOperation();
+            ops.forEach(op => {
+                if (TextOperation.isRetain(op)) operation.retain(op);
+                else if (TextOperation.isDelete(op)) operation.delete(op);
+                else if (TextOperation.isInsert(op)) operation.insert(op);
+                else throw new Error('Invalid operation component: ' + JSON.stringify(op));
+            });
+            return operation;
+        }
+
+        apply(doc) {
+            if (doc.length !== this.baseLength) throw new Error('Operation base length does not match document');
+            const parts = [];
+            let index = 0;
+            this.ops.forEach(op => {
+                if (TextOperation.isRetain(op)) { parts.push(doc.slice(index, index + op)); index += op; }
+                else if (TextOperation.isInsert(op)) parts.push(op);
+                else index -= op;
+            });
+            return parts.join('');
+        }
+
+        // Same contract as TextOperation::transform_position on the server.
+        transformPosition(position) {
+            let remaining = position;
+            let newPosition = position;
+            for (const op of this.ops) {
+                if (TextOperation.isRetain(op)) remaining -= op;
+                else if (TextOperation.isInsert(op)) newPosition += op.length;
+                else { newPosition -= Math.min(remaining, -op); remaining += op; }
+                if (remaining < 0) break;
+            }
+            return newPosition;
+        }
+
+        // Returns an operation with the same effect as applying this, then `other`.
+        compose(other) {
+            if (this.targetLength !== other.baseLength) throw new Error('Cannot compose: lengths differ');
+            const result = new TextOperation();
+            const ops1 = this.ops, ops2 = other.ops;
+            let i1 = 0, i2 = 0;
+            let op1 = ops1[i1++], op2 = ops2[i2++];
+            while (op1 !== undefined || op2 !== undefined) {
+                if (TextOperation.isDelete(op1)) { result.delete(op1); op1 = ops1[i1++]; continue; }
+                if (TextOperation.isInsert(op2)) { result.insert(op2); op2 = ops2[i2++]; continue; }
+                if (op1 === undefined || op2 === undefined) throw new Error('Cannot compose: operation too short');
+
+                if (TextOperation.isRetain(op1) && TextOperation.isRetain(op2)) {
+                    const n = Math.min(op1, op2);
+                    result.retain(n);
+                    op1 = op1 > n ? op1 - n : ops1[i1++];
+                    op2 = op2 > n ? op2 - n : ops2[i2++];
+                } else if (TextOperation.isInsert(op1) && TextOperation.isDelete(op2)) {
+                    const n = Math.min(op1.length, -op2);
+                    op1 = op1.length > n ? op1.slice(n) : ops1[i1++];
+                    op2 = -op2 > n ? op2 + n : ops2[i2++];
+                } else if (TextOperation.isInsert(op1) && TextOperation.isRetain(op2)) {
+                    const n = Math.min(op1.length, op2);
+                    result.insert(op1.slice(0, n));
+                    op1 = op1.length > n ? op1.slice(n) : ops1[i1++];
+                    op2 = op2 > n ? op2 - n : ops2[i2++];
+                } else {
+                    // retain followed by delete
+                    const n = Math.min(op1, -op2);
+                    result.delete(n);
+                    op1 = op1 > n ? op1 - n : ops1[i1++];
+                    op2 = -op2 > n ? op2 + n : ops2[i2++];
+                }
+            }
+            return result;
+        }
+
+        // Same contract as ot::transform on the server.
+        static transform(a, b) {
+            if (a.baseLength !== b.baseLength) throw new Error('Cannot transform: lengths differ');
+            const aPrime = new TextOperation(), bPrime = new TextOperation();
+            const ops1 = a.ops, ops2 = b.ops;
+            let i1 = 0, i2 = 0;
+            let op1 = ops1[i1++], op2 = ops2[i2++];
+            while (op1 !== undefined || op2 !== undefined) {
+                if (TextOperation.isInsert(op1)) { aPrime.insert(op1); bPrime.retain(op1.length); op1 = ops1[i1++]; continue; }
+                if (TextOperation.isInsert(op2)) { aPrime.retain(op2.length); bPrime.insert(op2); op2 = ops2[i2++]; continue; }
+                if (op1 === undefined || op2 === undefined) throw new Error('Cannot transform: operation too short');
+
+                const n = Math.min(Math.abs(op1), Math.abs(op2));
+                if (TextOperation.isRetain(op1) && TextOperation.isRetain(op2)) { aPrime.retain(n); bPrime.retain(n); }
+                else if (TextOperation.isDelete(op1) && TextOperation.isRetain(op2)) aPrime.delete(n);
+                else if (TextOperation.isRetain(op1) && TextOperation.isDelete(op2)) bPrime.delete(n);
+                op1 = Math.abs(op1) > n ? op1 + (op1 > 0 ? -n : n) : ops1[i1++];
+                op2 = Math.abs(op2) > n ? op2 + (op2 > 0 ? -n : n) : ops2[i2++];
+            }
+            return [aPrime, bPrime];
+        }
+
+        // Builds an operation from a Monaco content change event. `docLength`
+        // is the length of the document before the change.
+        static fromMonacoChanges(changes, docLength) {
+            const sorted = [...changes].sort((x, y) => x.rangeOffset - y.rangeOffset);
+            const operation = new TextOperation();
+            let index = 0;
+            sorted.forEach(change => {
+                operation.retain(change.rangeOffset - index);
+                operation.delete(change.rangeLength);
+                operation.insert(change.text);
+                index = change.rangeOffset + change.rangeLength;
+            });
+            operation.retain(docLength - index);
+            return operation;
+        }
+
+        // Converts the operation into Monaco edits against `model`.
+        toMonacoEdits(model) {
+            const edits = [];
+            let index = 0;
+            this.ops.forEach(op => {
+                if (TextOperation.isRetain(op)) {
+                    index += op;
+                } else if (TextOperation.isInsert(op)) {
+                    const pos = model.getPositionAt(index);
+                    edits.push({ range: new monaco.Range(pos.lineNumber, pos.column, pos.lineNumber, pos.column), text: op, forceMoveMarkers: true });
+                } else {
+                    const start = model.getPositionAt(index);
+                    const end = model.getPositionAt(index - op);
+                    edits.push({ range: new monaco.Range(start.lineNumber, start.column, end.lineNumber, end.column), text: '' });
+                    index -= op;
+                }
+            });
+            return edits;
+        }
+    }
+
+    // Client side of the server-authoritative protocol. At most one operation
+    // is in flight; local edits made meanwhile are buffered and composed.
+    class OTClient {
+        constructor(revision, sendOperation, applyOperation) {
+            this.revision = revision;
+            this.outstanding = null;
+            this.buffer = null;
+            this.sendOperation = sendOperation;
+            this.applyOperation = applyOperation;
+        }
+
+        applyClient(operation) {
+            if (this.outstanding === null) {
+                this.outstanding = operation;
+                this.sendOperation(this.revision, operation);
+            } else if (this.buffer === null) {
+                this.buffer = operation;
+            } else {
+                this.buffer = this.buffer.compose(operation);
+            }
+        }
+
+        applyServer(revision, operation) {
+            if (this.outstanding !== null) {
+                let pair = TextOperation.transform(this.outstanding, operation);
+                this.outstanding = pair[0];
+                operation = pair[1];
+                if (this.buffer !== null) {
+                    pair = TextOperation.transform(this.buffer, operation);
+                    this.buffer = pair[0];
+                    operation = pair[1];
+                }
+            }
+            this.revision = revision;
+            this.applyOperation(operation);
+        }
+
+        isSynchronized() {
+            return this.outstanding === null;
+        }
+
+        // Maps a position in the server's latest revision onto the local
+        // document, which may still contain unacknowledged edits.
+        transformPosition(position) {
+            if (this.outstanding !== null) position = this.outstanding.transformPosition(position);
+            if (this.buffer !== null) position = this.buffer.transformPosition(position);
+            return position;
+        }
+
+        serverAck(revision) {
+            this.revision = revision;
+            this.outstanding = this.buffer;
+            this.buffer = null;
+            if (this.outstanding !== null) this.sendOperation(this.revision, this.outstanding);
+        }
+    }
+
+    global.OT = { TextOperation, OTClient };
+})(window);
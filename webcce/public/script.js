@@ -31,13 +31,40 @@ document.getElementById("PingBackend").addEventListener("click", async () => {
 
 document.addEventListener('DOMContentLoaded', () => {
     const API_BASE_URL = 'https://api.mp2upnhs.my';
-    const ROOM_ID = 'public_room';
+    const SESSION_TOKEN = sessionStorage.getItem('webcce_token') || localStorage.getItem('webcce_token');
+    const ROOM_ID = sessionStorage.getItem('webcce_room') || localStorage.getItem('webcce_room') || 'public_room';
+
+    if (!SESSION_TOKEN) {
+        window.location.href = './login.html';
+        return;
+    }
+
+    async function apiFetch(path, options = {}) {
+        const headers = { ...(options.headers || {}), 'Authorization': `Bearer ${SESSION_TOKEN}` };
+        const response = await fetch(`${API_BASE_URL}${path}`, { ...options, headers });
+        if (response.status === 401) {
+            sessionStorage.removeItem('webcce_token');
+            localStorage.removeItem('webcce_token');
+            window.location.href = './login.html';
+        }
+        return response;
+    }
 
     let monacoEditor;
     let currentWebSocket;
+    let otClient;
+    let pendingMessages = [];
+    let keepAliveTimer;
+    let pendingSelection = null;
+    let saveRequested = false;
+    // Other people in the current file: username -> { id, color, decorations }.
+    const remoteUsers = new Map();
+    let nextRemoteUserId = 0;
     let currentFileId;
     let isUpdatingEditor = false;
     const fileContentCache = new Map();
+    // Must match PROTOCOL_VERSION in backend/src/protocol.rs.
+    const PROTOCOL_VERSION = 1;
 
     const fileManager = document.getElementById("file-manager");
     const fileTreeContainer = document.getElementById("file-tree");
@@ -48,6 +75,9 @@ document.addEventListener('DOMContentLoaded', () => {
     const saveButton = document.getElementById("save-button");
     const resizerFmEd = document.getElementById("resizer-fm-ed");
     const resizerEdPv = document.getElementById("resizer-ed-pv");
+    const presenceList = document.getElementById("presence-list");
+    const remoteCursorStyles = document.createElement('style');
+    document.head.appendChild(remoteCursorStyles);
 
 
     require.config({ paths: { 'vs': 'https://cdn.jsdelivr.net/npm/monaco-editor@0.45.0/min/vs' }});
@@ -59,52 +89,120 @@ document.addEventListener('DOMContentLoaded', () => {
             automaticLayout: true,
         });
 
-        monacoEditor.onDidChangeModelContent(() => {
+        monacoEditor.onDidChangeModelContent((event) => {
             if (isUpdatingEditor) return;
             const content = monacoEditor.getValue();
             fileContentCache.set(currentFileId, content);
-            updatePreview();
-            if (currentWebSocket && currentWebSocket.readyState === WebSocket.OPEN) {
-                currentWebSocket.send(content);
+            if (otClient) {
+                let previousLength = content.length;
+                event.changes.forEach(change => { previousLength += change.rangeLength - change.text.length; });
+                otClient.applyClient(OT.TextOperation.fromMonacoChanges(event.changes, previousLength));
             }
         });
+
+        monacoEditor.onDidChangeCursorSelection((event) => {
+            if (isUpdatingEditor || !currentFileId) return;
+            const model = monacoEditor.getModel();
+            const selection = event.selection;
+            pendingSelection = {
+                anchor: model.getOffsetAt({ lineNumber: selection.selectionStartLineNumber, column: selection.selectionStartColumn }),
+                head: model.getOffsetAt(selection.getPosition()),
+            };
+            flushSelection();
+        });
     });
 
+    // The preview loads the project from the backend's preview route, so
+    // pages can use relative scripts, styles, images and links like the
+    // deployed site. It is only navigated when the page changes; edits reach
+    // it through the project's event stream.
+    let previewUrl;
     function updatePreview() {
         if (!currentFileId) return;
         const currentFile = findFileInTree(currentFileId);
         if (!currentFile) return;
-        const projectNode = currentFile.projectNode;
-        const projectFiles = Array.from(projectNode.querySelectorAll('.file-name'));
-        const htmlFileDiv = projectFiles.find(div => div.textContent.endsWith('.html'));
+        const projectId = currentFile.projectNode.dataset.projectId;
+        subscribeToProject(projectId);
+        const page = currentFile.path.endsWith('.html') ? currentFile.path : '';
+        // Other files keep showing whichever page of the project is open.
+        if (!page && previewUrl) return;
+        showInPreview(projectId, page);
+    }
 
-        if (!htmlFileDiv) {
-            previewFrame.srcdoc = `<html><body style='color: #888; font-family: sans-serif; padding: 20px;'>No HTML file to preview in this project.</body></html>`;
-            return;
-        }
+    // The preview is opened with a short-lived token that only grants access
+    // to one project's preview, never the session token. It is renewed when
+    // the preview navigates or reloads with less than PREVIEW_TOKEN_MARGIN left.
+    const PREVIEW_TOKEN_MARGIN = 15 * 60;
+    let previewToken;
+    function previewTokenIsFresh(projectId) {
+        return previewToken && previewToken.projectId === projectId
+            && previewToken.expiresAt - Date.now() / 1000 > PREVIEW_TOKEN_MARGIN;
+    }
 
-        const htmlFileId = parseInt(htmlFileDiv.dataset.fileId);
-        let htmlContent = fileContentCache.get(htmlFileId) || '';
-        const cssFileDivs = projectFiles.filter(div => div.textContent.endsWith('.css'));
-        let cssContent = '';
-        cssFileDivs.forEach(cssFileDiv => {
-            const cssFileId = parseInt(cssFileDiv.dataset.fileId);
-            if (fileContentCache.has(cssFileId)) {
-                cssContent += fileContentCache.get(cssFileId) + '\n';
-            }
-        });
+    async function previewTokenFor(projectId) {
+        if (previewTokenIsFresh(projectId)) return previewToken.token;
+        const response = await apiFetch(`/api/project/${projectId}/preview-token`, { method: 'POST' });
+        if (!response.ok) return null;
+        const { token, expires_at } = await response.json();
+        previewToken = { projectId, token, expiresAt: expires_at };
+        return token;
+    }
+
+    let previewPage;
+    async function showInPreview(projectId, path) {
+        subscribeToProject(projectId);
+        const target = `${projectId}/${path}`;
+        if (previewUrl && previewPage === target && previewTokenIsFresh(projectId)) return;
+        previewPage = target;
+        const token = await previewTokenFor(projectId);
+        // Another page may have been opened while the token was on its way.
+        if (!token || previewPage !== target) return;
+        const page = path.split('/').map(encodeURIComponent).join('/');
+        previewUrl = `${API_BASE_URL}/preview/${token}/${page}`;
+        previewFrame.src = previewUrl;
+    }
 
-        const finalHtml = `
-            <html>
-                <head><style>${cssContent}</style></head>
-                <body>${htmlContent}</body>
-            </html>`;
-        previewFrame.srcdoc = finalHtml;
+    // Saves, live edits and tree changes anywhere in the project are pushed
+    // over server-sent events and relayed to the preview page, which reloads
+    // itself or swaps the changed stylesheet. Bursts of edits are batched.
+    let projectEvents;
+    let previewProjectId;
+    let previewChangeTimer;
+    let previewChangedPaths = new Set();
+    function subscribeToProject(projectId) {
+        if (previewProjectId === projectId) return;
+        if (projectEvents) projectEvents.close();
+        previewProjectId = projectId;
+        previewUrl = null;
+        projectEvents = new EventSource(`${API_BASE_URL}/api/project/${projectId}/events?token=${encodeURIComponent(SESSION_TOKEN)}`);
+        projectEvents.addEventListener('content', (event) => queuePreviewChange(JSON.parse(event.data).path));
+        projectEvents.addEventListener('tree', () => queuePreviewChange(null));
+    }
+
+    function queuePreviewChange(path) {
+        previewChangedPaths.add(path);
+        clearTimeout(previewChangeTimer);
+        previewChangeTimer = setTimeout(() => {
+            const paths = [...previewChangedPaths];
+            previewChangedPaths = new Set();
+            // Only stylesheets can be swapped without a reload.
+            const onlyCss = paths.every(p => p && p.toLowerCase().endsWith('.css'));
+            // A reload with a stale token would fail; navigate with a new one instead.
+            if (!onlyCss && previewPage && !previewTokenIsFresh(previewProjectId)) {
+                const [projectId, ...path] = previewPage.split('/');
+                showInPreview(projectId, path.join('/'));
+                return;
+            }
+            const targets = onlyCss ? paths : [paths.find(p => !p || !p.toLowerCase().endsWith('.css'))];
+            // The sandboxed preview has an opaque origin, so no narrower target
+            // origin matches it; the message only carries a file path.
+            targets.forEach(p => previewFrame.contentWindow?.postMessage({ type: 'webcce:file-changed', path: p }, '*'));
+        }, 500);
     }
 
     async function fetchFileTree() {
         try {
-            const response = await fetch(`${API_BASE_URL}/api/file-tree/${ROOM_ID}`);
+            const response = await apiFetch(`/api/file-tree/${ROOM_ID}`);
             if (!response.ok) throw new Error(`HTTP error! status: ${response.status}`);
             const projects = await response.json();
             renderFileTree(projects);
@@ -119,46 +217,91 @@ document.addEventListener('DOMContentLoaded', () => {
         projects.forEach(project => {
             const projectContainer = document.createElement('div');
             projectContainer.className = 'project-container';
+            projectContainer.dataset.projectId = project.id;
             const projectDiv = document.createElement('div');
             projectDiv.className = 'project-name';
             projectDiv.textContent = project.name;
+            const uploadButton = document.createElement('button');
+            uploadButton.className = 'upload-button';
+            uploadButton.textContent = 'Upload';
+            uploadButton.dataset.projectId = project.id;
+            projectDiv.appendChild(uploadButton);
+            const exportButton = document.createElement('button');
+            exportButton.className = 'export-button';
+            exportButton.textContent = 'ZIP';
+            exportButton.dataset.projectId = project.id;
+            projectDiv.appendChild(exportButton);
             projectContainer.appendChild(projectDiv);
             const filesContainer = document.createElement('div');
             filesContainer.className = 'project-files';
-            project.files.forEach(file => {
-                const fileDiv = document.createElement('div');
-                fileDiv.className = 'file-name';
-                fileDiv.textContent = file.name;
-                fileDiv.dataset.fileId = file.id;
-                filesContainer.appendChild(fileDiv);
-            });
+            renderTreeNodes(project.children, filesContainer);
             projectContainer.appendChild(filesContainer);
             fileTreeContainer.appendChild(projectContainer);
         });
     }
 
+    function renderTreeNodes(nodes, container) {
+        nodes.forEach(node => {
+            if (node.type === 'folder') {
+                const folderContainer = document.createElement('div');
+                folderContainer.className = 'folder-container';
+                const folderDiv = document.createElement('div');
+                folderDiv.className = 'folder-name';
+                folderDiv.textContent = node.name;
+                folderDiv.dataset.path = node.path;
+                folderContainer.appendChild(folderDiv);
+                const childrenContainer = document.createElement('div');
+                childrenContainer.className = 'folder-children';
+                renderTreeNodes(node.children, childrenContainer);
+                folderContainer.appendChild(childrenContainer);
+                container.appendChild(folderContainer);
+            } else {
+                const fileDiv = document.createElement('div');
+                fileDiv.className = 'file-name';
+                fileDiv.textContent = node.name;
+                fileDiv.dataset.fileId = node.id;
+                fileDiv.dataset.path = node.path;
+                if (node.binary) {
+                    fileDiv.dataset.binary = 'true';
+                    fileDiv.classList.add('binary-file');
+                }
+                container.appendChild(fileDiv);
+            }
+        });
+    }
+
     async function loadFile(fileId) {
         if (currentFileId === fileId) return;
+        // Images, fonts and audio can't be edited; show them in the preview.
+        const currentFile = findFileInTree(fileId);
+        // The file was removed since the tree was drawn.
+        if (!currentFile) {
+            fetchFileTree();
+            return;
+        }
+        if (currentFile.binary) {
+            showInPreview(currentFile.projectNode.dataset.projectId, currentFile.path);
+            return;
+        }
         saveButton.disabled = true;
         isUpdatingEditor = true;
 
-        const currentFile = findFileInTree(fileId);
-        const projectNode = currentFile.projectNode;
-        if (!projectNode.dataset.loaded) {
-            const projectFiles = Array.from(projectNode.querySelectorAll('.file-name'));
-            for (const fileDiv of projectFiles) {
-                const id = parseInt(fileDiv.dataset.fileId);
-                if (!fileContentCache.has(id)) {
-                    const response = await fetch(`${API_BASE_URL}/api/file/${id}`);
-                    const file = await response.json();
-                    fileContentCache.set(id, file.content);
-                }
+        if (!fileContentCache.has(fileId)) {
+            const response = await apiFetch(`/api/file/${fileId}`);
+            if (!response.ok) {
+                console.error(`Failed to load file ${fileId}: status ${response.status}`);
+                isUpdatingEditor = false;
+                saveButton.disabled = !currentFileId;
+                fetchFileTree();
+                return;
             }
-            projectNode.dataset.loaded = 'true';
+            const file = await response.json();
+            fileContentCache.set(fileId, file.content);
         }
 
         const content = fileContentCache.get(fileId);
         monacoEditor.setValue(content || '');
+        monacoEditor.getModel().setEOL(monaco.editor.EndOfLineSequence.LF);
         const language = getLanguageForFileName(currentFile.name);
         monaco.editor.setModelLanguage(monacoEditor.getModel(), language);
         currentFileId = fileId;
@@ -175,32 +318,205 @@ document.addEventListener('DOMContentLoaded', () => {
         return {
             id: fileId,
             name: fileDiv.textContent,
+            path: fileDiv.dataset.path,
+            binary: fileDiv.dataset.binary === 'true',
             projectNode: fileDiv.closest('.project-container')
         };
     }
 
     function connectWebSocket(fileId) {
         if (currentWebSocket) currentWebSocket.close();
+        clearInterval(keepAliveTimer);
+        pendingMessages = [];
+        pendingSelection = null;
+        saveRequested = false;
+        clearRemoteUsers();
         const wsProtocol = API_BASE_URL.startsWith('https://') ? 'wss://' : 'ws://';
         const wsHost = API_BASE_URL.replace(/^https?:\/\//, '');
-        const username = `User_${Math.floor(Math.random() * 1000)}`;
-        const wsUrl = `${wsProtocol}${wsHost}/ws/${fileId}/${username}`;
-        currentWebSocket = new WebSocket(wsUrl);
-        currentWebSocket.onopen = () => console.log("WebSocket connection established.");
-        currentWebSocket.onmessage = (event) => {
-            const receivedContent = event.data;
-            if (monacoEditor.getValue() !== receivedContent) {
-                isUpdatingEditor = true;
-                const currentPosition = monacoEditor.getPosition();
-                fileContentCache.set(currentFileId, receivedContent);
-                monacoEditor.setValue(receivedContent);
-                monacoEditor.setPosition(currentPosition);
-                isUpdatingEditor = false;
-                updatePreview();
+        // The server takes the username from the session token.
+        const wsUrl = `${wsProtocol}${wsHost}/ws/${fileId}?token=${encodeURIComponent(SESSION_TOKEN)}`;
+        const socket = new WebSocket(wsUrl);
+        currentWebSocket = socket;
+        // Until the snapshot arrives we don't know which revision to base
+        // edits on, so the editor stays read-only.
+        otClient = null;
+        monacoEditor.updateOptions({ readOnly: true });
+        socket.onopen = () => {
+            console.log("WebSocket connection established.");
+            socket.send(JSON.stringify({ type: 'join', version: PROTOCOL_VERSION }));
+            pendingMessages.forEach(message => socket.send(message));
+            pendingMessages = [];
+            // The revision tells the server which older operations it can forget.
+            keepAliveTimer = setInterval(() => sendMessage({ type: 'ping', revision: otClient?.revision }), 30000);
+        };
+        socket.onmessage = (event) => {
+            const message = JSON.parse(event.data);
+            switch (message.type) {
+                case 'snapshot':
+                    isUpdatingEditor = true;
+                    if (monacoEditor.getValue() !== message.content) {
+                        const position = monacoEditor.getPosition();
+                        monacoEditor.setValue(message.content);
+                        monacoEditor.setPosition(position);
+                    }
+                    isUpdatingEditor = false;
+                    fileContentCache.set(currentFileId, message.content);
+                    otClient = new OT.OTClient(message.revision, sendOperation, applyRemoteOperation);
+                    monacoEditor.updateOptions({ readOnly: false });
+                    break;
+                case 'presence':
+                    message.users.forEach(user => addRemoteUser(user.username, user.color, user.selection));
+                    break;
+                case 'join':
+                    addRemoteUser(message.username, message.color, null);
+                    break;
+                case 'leave':
+                    removeRemoteUser(message.username);
+                    break;
+                case 'cursor':
+                    setRemoteSelection(message.username, message.position, message.position);
+                    break;
+                case 'selection':
+                    setRemoteSelection(message.username, message.anchor, message.head);
+                    break;
+                case 'ack':
+                    otClient.serverAck(message.revision);
+                    flushSelection();
+                    break;
+                case 'edit':
+                    otClient.applyServer(message.revision, OT.TextOperation.fromJSON(message.operation));
+                    break;
+                case 'saved':
+                    // Autosaves (username null) and other people's saves are silent.
+                    if (saveRequested && message.username !== null) {
+                        saveRequested = false;
+                        showSaveStatus('Saved!');
+                    }
+                    break;
+                case 'error':
+                    console.error(`Server error (${message.code}):`, message.message);
+                    if (message.code === 'save_failed') {
+                        saveRequested = false;
+                        showSaveStatus('Error!');
+                    }
+                    // Someone deleted the open file; the server closes the socket.
+                    if (message.code === 'file_deleted') {
+                        fileContentCache.delete(currentFileId);
+                        currentFileId = null;
+                        otClient = null;
+                        monacoEditor.updateOptions({ readOnly: true });
+                        saveButton.disabled = true;
+                        fetchFileTree();
+                    }
+                    break;
             }
         };
-        currentWebSocket.onerror = (error) => console.error("WebSocket error:", error);
-        currentWebSocket.onclose = () => console.log("WebSocket connection closed.");
+        socket.onerror = (error) => console.error("WebSocket error:", error);
+        socket.onclose = () => {
+            console.log("WebSocket connection closed.");
+            if (currentWebSocket === socket) clearInterval(keepAliveTimer);
+        };
+    }
+
+    function sendMessage(message) {
+        const text = JSON.stringify(message);
+        if (currentWebSocket.readyState === WebSocket.OPEN) currentWebSocket.send(text);
+        else pendingMessages.push(text);
+    }
+
+    function sendOperation(revision, operation) {
+        sendMessage({ type: 'edit', revision, operation });
+    }
+
+    // Selections are only sent while no local edit is in flight, so their
+    // offsets always refer to a revision the server knows.
+    function flushSelection() {
+        if (!pendingSelection || !otClient || !otClient.isSynchronized()) return;
+        const { anchor, head } = pendingSelection;
+        pendingSelection = null;
+        if (anchor === head) sendMessage({ type: 'cursor', position: head });
+        else sendMessage({ type: 'selection', anchor, head });
+    }
+
+    function addRemoteUser(username, color, selection) {
+        if (!/^#[0-9a-fA-F]{6}$/.test(color)) color = '#888888';
+        let user = remoteUsers.get(username);
+        if (!user) {
+            user = { id: nextRemoteUserId++, color, decorations: monacoEditor.createDecorationsCollection() };
+            remoteUsers.set(username, user);
+        }
+        user.color = color;
+        renderRemoteUsers();
+        if (selection) setRemoteSelection(username, selection.anchor, selection.head);
+    }
+
+    function removeRemoteUser(username) {
+        const user = remoteUsers.get(username);
+        if (!user) return;
+        user.decorations.clear();
+        remoteUsers.delete(username);
+        renderRemoteUsers();
+    }
+
+    function clearRemoteUsers() {
+        remoteUsers.forEach(user => user.decorations.clear());
+        remoteUsers.clear();
+        renderRemoteUsers();
+    }
+
+    function setRemoteSelection(username, anchor, head) {
+        const user = remoteUsers.get(username);
+        if (!user || !otClient) return;
+        const model = monacoEditor.getModel();
+        anchor = otClient.transformPosition(anchor);
+        head = otClient.transformPosition(head);
+        const start = model.getPositionAt(Math.min(anchor, head));
+        const end = model.getPositionAt(Math.max(anchor, head));
+        const caret = model.getPositionAt(head);
+        const className = `remote-user-${user.id}`;
+        const decorations = [{
+            range: new monaco.Range(caret.lineNumber, caret.column, caret.lineNumber, caret.column),
+            options: { beforeContentClassName: `remote-caret ${className}-caret`, stickiness: monaco.editor.TrackedRangeStickiness.NeverGrowsWhenTypingAtEdges },
+        }];
+        if (anchor !== head) {
+            decorations.push({
+                range: new monaco.Range(start.lineNumber, start.column, end.lineNumber, end.column),
+                options: { className: `${className}-selection` },
+            });
+        }
+        user.decorations.set(decorations);
+    }
+
+    // Usernames end up in CSS `content`, so every character is escaped.
+    function cssString(text) {
+        return '"' + Array.from(text).map(c => `\\${c.codePointAt(0).toString(16)} `).join('') + '"';
+    }
+
+    // Rebuilds the per-user caret styles and the list of people in the file.
+    function renderRemoteUsers() {
+        const rules = [];
+        presenceList.innerHTML = '';
+        remoteUsers.forEach((user, username) => {
+            const className = `remote-user-${user.id}`;
+            rules.push(`.${className}-caret { border-left: 2px solid ${user.color}; }`);
+            rules.push(`.${className}-caret::after { content: ${cssString(username)}; background-color: ${user.color}; }`);
+            rules.push(`.${className}-selection { background-color: ${user.color}44; }`);
+
+            const entry = document.createElement('span');
+            entry.className = 'presence-user';
+            entry.textContent = username;
+            entry.style.borderColor = user.color;
+            presenceList.appendChild(entry);
+        });
+        remoteCursorStyles.textContent = rules.join('\n');
+    }
+
+    function applyRemoteOperation(operation) {
+        const model = monacoEditor.getModel();
+        isUpdatingEditor = true;
+        model.applyEdits(operation.toMonacoEdits(model));
+        isUpdatingEditor = false;
+        fileContentCache.set(currentFileId, monacoEditor.getValue());
     }
 
     function getLanguageForFileName(fileName) {
@@ -221,35 +537,107 @@ document.addEventListener('DOMContentLoaded', () => {
             const fileId = parseInt(event.target.dataset.fileId);
             if (fileId) loadFile(fileId);
         }
-        if (event.target && event.target.matches('.project-name')) {
-            const projectDiv = event.target;
-            const filesContainer = projectDiv.nextElementSibling;
-            projectDiv.classList.toggle('collapsed');
-            filesContainer.classList.toggle('collapsed');
+        if (event.target && event.target.matches('.export-button')) {
+            exportProject(event.target.dataset.projectId);
+            return;
+        }
+        if (event.target && event.target.matches('.upload-button')) {
+            uploadInput.dataset.projectId = event.target.dataset.projectId;
+            uploadInput.click();
+            return;
+        }
+        if (event.target && event.target.matches('.project-name, .folder-name')) {
+            const headerDiv = event.target;
+            const childrenContainer = headerDiv.nextElementSibling;
+            headerDiv.classList.toggle('collapsed');
+            childrenContainer.classList.toggle('collapsed');
         }
     });
 
-    saveButton.addEventListener('click', async () => {
-        if (!currentFileId) return;
-        saveButton.textContent = 'Saving...';
-        const content = fileContentCache.get(currentFileId);
+    // Files are uploaded into the root of the project whose Upload button
+    // was clicked. Text files become editable files, anything else is kept
+    // as a binary asset.
+    const uploadInput = document.createElement('input');
+    uploadInput.type = 'file';
+    uploadInput.multiple = true;
+    uploadInput.hidden = true;
+    document.body.appendChild(uploadInput);
+    uploadInput.addEventListener('change', async () => {
+        if (uploadInput.files.length === 0) return;
+        const form = new FormData();
+        form.append('project_id', uploadInput.dataset.projectId);
+        for (const file of uploadInput.files) form.append('file', file);
+        uploadInput.value = '';
         try {
-            const response = await fetch(`${API_BASE_URL}/api/file/save`, {
-                method: 'POST',
-                headers: { 'Content-Type': 'application/json' },
-                body: JSON.stringify({ id: currentFileId, content: content }),
-            });
-            if (response.ok) {
-                saveButton.textContent = 'Saved!';
-                setTimeout(() => { saveButton.textContent = 'Save'; }, 2000);
-            } else { throw new Error('Save failed'); }
+            const response = await apiFetch('/api/file/upload', { method: 'POST', body: form });
+            if (!response.ok) {
+                alert(`Upload failed: ${await response.text()}`);
+                return;
+            }
+            fetchFileTree();
+        } catch (error) {
+            console.error("Upload failed:", error);
+            alert('Upload failed.');
+        }
+    });
+
+    // The export needs the Authorization header, so it is fetched and handed
+    // to the browser as a blob instead of being linked to directly.
+    async function exportProject(projectId) {
+        const response = await apiFetch(`/api/project/${projectId}/export`);
+        if (!response.ok) {
+            alert(`Export failed: ${await response.text()}`);
+            return;
+        }
+        const disposition = response.headers.get('Content-Disposition') || '';
+        const match = disposition.match(/filename="([^"]+)"/);
+        const link = document.createElement('a');
+        link.href = URL.createObjectURL(await response.blob());
+        link.download = match ? match[1] : 'project.zip';
+        link.click();
+        URL.revokeObjectURL(link.href);
+    }
+
+    // Importing a ZIP creates a new project named after the archive.
+    const importInput = document.createElement('input');
+    importInput.type = 'file';
+    importInput.accept = '.zip,application/zip';
+    importInput.hidden = true;
+    document.body.appendChild(importInput);
+    document.getElementById('import-button').addEventListener('click', () => importInput.click());
+    importInput.addEventListener('change', async () => {
+        if (importInput.files.length === 0) return;
+        const form = new FormData();
+        form.append('file', importInput.files[0]);
+        importInput.value = '';
+        try {
+            const response = await apiFetch('/api/project/import', { method: 'POST', body: form });
+            if (!response.ok) {
+                alert(`Import failed: ${await response.text()}`);
+                return;
+            }
+            fetchFileTree();
         } catch (error) {
-            console.error("Failed to save file:", error);
-            saveButton.textContent = 'Error!';
-            setTimeout(() => { saveButton.textContent = 'Save'; }, 2000);
+            console.error("Import failed:", error);
+            alert('Import failed.');
         }
     });
 
+    function showSaveStatus(text) {
+        saveButton.textContent = text;
+        setTimeout(() => { saveButton.textContent = 'Save'; }, 2000);
+    }
+
+    // The server saves its live copy of the document and answers with
+    // "saved" (or an error), which the socket handler reports. Edits are
+    // also autosaved, so this only forces a save right now.
+    saveButton.addEventListener('click', () => {
+        if (!currentFileId || !currentWebSocket) return;
+        saveButton.textContent = 'Saving...';
+        saveRequested = true;
+        sendMessage({ type: 'save' });
+    });
+
     function makeResizable(resizer, leftPanel, rightPanel) {
         const minWidth = 100;
         let x = 0;